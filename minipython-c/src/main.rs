@@ -1,39 +1,347 @@
-use clap::{App, Arg, ArgMatches};
-use minipython::compiler::CompilerInstance;
-use std::path::Path;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use minipython::compiler::{format_program, render_diagnostics, CheckOptions, CodegenOptions, CompilerInstance, DecrementPolicy, Diagnostic, InputSource, IntegerWidth, Label, InterpreterInstance, OutputFormat, OverflowPolicy, ProgramOutput, Target, ValueRepresentation, VirtualMachineInstance};
+use std::path::{Path, PathBuf};
+use std::fs;
+use serde_json::{json, Value};
+
+//Shared by compiling and running, as both need the imported modules
+fn include_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("INCLUDE")
+        .short("I")
+        .help("Adds a directory to search for imported modules, after the directory of the input file")
+        .value_name("DIR")
+        .takes_value(true)
+        .multiple(true)
+        .number_of_values(1)
+}
+
+fn no_prelude_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("NO_PRELUDE")
+        .long("no-prelude")
+        .help("Disables the built-in prelude functions, e.g. add and mul")
+}
 
 fn parse_args<'a>() -> ArgMatches<'a> {
     App::new("MiniPython compiler")
         .about("Compiles MiniPython programs")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
         .arg(Arg::with_name("OUT")
             .short("o")
             .long("out")
             .help("Sets the output file name")
             .value_name("FILE")
             .takes_value(true))
+        .arg(Arg::with_name("EMIT")
+            .long("emit")
+            .help("Sets the kind of output to generate, llvm needs LLVM 15 or newer")
+            .value_name("TARGET")
+            .possible_values(&["c", "c-lib", "llvm", "wat", "asm", "bytecode", "rust"])
+            .default_value("c")
+            .takes_value(true))
+        .arg(Arg::with_name("PREFIX")
+            .long("prefix")
+            .help("Sets the prefix of the symbols exported with --emit c-lib, defaults to the file name")
+            .value_name("PREFIX")
+            .takes_value(true))
+        .arg(Arg::with_name("BIGNUM")
+            .long("bignum")
+            .help("Uses arbitrary-precision values in the generated C code"))
+        .arg(Arg::with_name("SATURATING_DECREMENT")
+            .long("saturating-decrement")
+            .help("Makes decrementing zero result in zero in the generated C code"))
+        .arg(Arg::with_name("WIDTH")
+            .long("width")
+            .help("Sets the bit width of values in the generated C code")
+            .value_name("BITS")
+            .possible_values(&["8", "16", "32", "64", "128"])
+            .default_value("64")
+            .takes_value(true))
+        .arg(Arg::with_name("OVERFLOW")
+            .long("overflow")
+            .help("Sets what happens when an increment overflows in the generated C code")
+            .value_name("POLICY")
+            .possible_values(&["wrap", "saturate", "trap"])
+            .default_value("wrap")
+            .takes_value(true))
+        .arg(Arg::with_name("INPUT_SOURCE")
+            .long("input-source")
+            .help("Sets where the generated C program reads its inputs from")
+            .value_name("SOURCE")
+            .possible_values(&["prompt", "args", "env"])
+            .default_value("prompt")
+            .takes_value(true))
+        .arg(Arg::with_name("OUTPUT_FORMAT")
+            .long("output-format")
+            .help("Sets how the generated C program prints its output")
+            .value_name("FORMAT")
+            .possible_values(&["name-value", "plain", "json"])
+            .default_value("name-value")
+            .takes_value(true))
+        .arg(Arg::with_name("MESSAGE_FORMAT")
+            .long("message-format")
+            .help("Sets how errors are reported")
+            .value_name("FORMAT")
+            .possible_values(&["human", "json"])
+            .default_value("human")
+            .takes_value(true))
+        .arg(Arg::with_name("WARNINGS_AS_ERRORS")
+            .long("warnings-as-errors")
+            .help("Fails the compilation if there are warnings"))
+        .arg(Arg::with_name("DENY_RECURSION")
+            .long("deny-recursion")
+            .help("Fails the compilation if functions call themselves, directly or through other functions"))
+        .arg(include_arg())
+        .arg(no_prelude_arg())
         .arg(Arg::with_name("INPUT")
             .help("Input file")
             .required(true)
             .value_name("FILE")
             .index(1))
+        .subcommand(SubCommand::with_name("run")
+            .about("Runs a MiniPython program with the interpreter")
+            .arg(include_arg())
+            .arg(no_prelude_arg())
+            .arg(Arg::with_name("INPUT")
+                .help("Input file")
+                .required(true)
+                .value_name("FILE")
+                .index(1))
+            .arg(Arg::with_name("VALUES")
+                .help("Values for the program inputs")
+                .value_name("VALUE")
+                .multiple(true)
+                .index(2)))
+        .subcommand(SubCommand::with_name("fmt")
+            .about("Formats a MiniPython program in place")
+            .arg(Arg::with_name("CHECK")
+                .long("check")
+                .help("Only checks whether the file is formatted, without changing it"))
+            .arg(Arg::with_name("INPUT")
+                .help("Input file")
+                .required(true)
+                .value_name("FILE")
+                .index(1)))
+        .subcommand(SubCommand::with_name("exec")
+            .about("Executes a compiled MiniPython bytecode file")
+            .arg(Arg::with_name("INPUT")
+                .help("Bytecode file")
+                .required(true)
+                .value_name("FILE")
+                .index(1))
+            .arg(Arg::with_name("VALUES")
+                .help("Values for the program inputs")
+                .value_name("VALUE")
+                .multiple(true)
+                .index(2)))
         .get_matches()
 }
 
-fn compile(matches: ArgMatches) -> Result<(), String> {
-    let input_path = Path::new(matches.value_of("INPUT").unwrap());
-    let default_output = input_path.with_extension("c");
+fn parse_target(matches: &ArgMatches) -> Target {
+    match matches.value_of("EMIT").unwrap() {
+        "c-lib" => Target::CLib,
+        "llvm" => Target::Llvm,
+        "wat" => Target::Wat,
+        "asm" => Target::Asm,
+        "bytecode" => Target::Bytecode,
+        "rust" => Target::Rust,
+        _ => Target::C
+    }
+}
+
+fn parse_search_paths(matches: &ArgMatches) -> Vec<PathBuf> {
+    matches.values_of("INCLUDE").map(|dirs| dirs.map(PathBuf::from).collect()).unwrap_or_default()
+}
+
+fn parse_codegen_options(matches: &ArgMatches) -> CodegenOptions {
+    let representation = if matches.is_present("BIGNUM") { ValueRepresentation::BigNum } else { ValueRepresentation::Native };
+    let width = match matches.value_of("WIDTH").unwrap() {
+        "8" => IntegerWidth::W8,
+        "16" => IntegerWidth::W16,
+        "32" => IntegerWidth::W32,
+        "128" => IntegerWidth::W128,
+        _ => IntegerWidth::W64
+    };
+    let overflow = match matches.value_of("OVERFLOW").unwrap() {
+        "saturate" => OverflowPolicy::Saturate,
+        "trap" => OverflowPolicy::Trap,
+        _ => OverflowPolicy::Wrap
+    };
+    let decrement = if matches.is_present("SATURATING_DECREMENT") { DecrementPolicy::Saturate } else { DecrementPolicy::Wrap };
+    let input_source = match matches.value_of("INPUT_SOURCE").unwrap() {
+        "args" => InputSource::Arguments,
+        "env" => InputSource::Environment,
+        _ => InputSource::Prompt
+    };
+    let output_format = match matches.value_of("OUTPUT_FORMAT").unwrap() {
+        "plain" => OutputFormat::Plain,
+        "json" => OutputFormat::Json,
+        _ => OutputFormat::NameValue
+    };
+    CodegenOptions {
+        representation,
+        width,
+        overflow,
+        decrement,
+        input_source,
+        output_format
+    }
+}
+
+//Returns the warnings of a successful compilation
+fn compile(matches: &ArgMatches, input_path: &Path) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let target = parse_target(matches);
+    let default_output = input_path.with_extension(target.extension());
     let output_file_path = matches
         .value_of("OUT")
         .map(Path::new)
         .unwrap_or_else(|| default_output.as_path());
-    let mut compiler = CompilerInstance::new(input_path, output_file_path)?;
+    let options = parse_codegen_options(matches);
+    let checks = CheckOptions {
+        warnings_as_errors: matches.is_present("WARNINGS_AS_ERRORS"),
+        deny_recursion: matches.is_present("DENY_RECURSION")
+    };
+    let mut compiler = CompilerInstance::new(input_path, output_file_path, target, options, checks).map_err(|e| vec![Diagnostic::error(e)])?
+        .with_search_paths(parse_search_paths(matches))
+        .with_prelude(!matches.is_present("NO_PRELUDE"));
+    if let Some(prefix) = matches.value_of("PREFIX") {
+        compiler = compiler.with_symbol_prefix(prefix).map_err(|e| vec![Diagnostic::error(e)])?;
+    }
     compiler.run()
 }
 
+fn position_to_json(line: usize, col: usize) -> Value {
+    json!({ "line": line, "col": col })
+}
+
+fn label_to_json(label: &Label) -> Value {
+    json!({
+        "message": label.message,
+        "start": position_to_json(label.span.start.line, label.span.start.col),
+        "end": position_to_json(label.span.end.line, label.span.end.col)
+    })
+}
+
+//One JSON object per line, so the output can be processed while it is written
+fn diagnostic_to_json(diagnostic: &Diagnostic, input_path: &Path) -> Value {
+    json!({
+        "severity": diagnostic.severity.to_string(),
+        "code": diagnostic.code,
+        "message": diagnostic.message,
+        "file": diagnostic.file.as_deref().unwrap_or(input_path).display().to_string(),
+        "primary": diagnostic.primary.as_ref().map(label_to_json),
+        "secondary": diagnostic.secondary.iter().map(label_to_json).collect::<Vec<Value>>(),
+        "notes": diagnostic.notes
+    })
+}
+
+fn report(matches: &ArgMatches, input_path: &Path, diagnostics: &[Diagnostic]) {
+    match matches.value_of("MESSAGE_FORMAT") {
+        Some("json") => for diagnostic in diagnostics {
+            println!("{}", diagnostic_to_json(diagnostic, input_path));
+        },
+        _ => eprint!("{}", render_diagnostics(input_path, diagnostics))
+    }
+}
+
+fn parse_input_values(matches: &ArgMatches) -> Result<Vec<u64>, String> {
+    matches
+        .values_of("VALUES")
+        .map(|values| values.map(|v| v.parse::<u64>().map_err(|_| format!("Invalid input value: {}", v))).collect())
+        .unwrap_or_else(|| Ok(Vec::new()))
+}
+
+fn run(matches: &ArgMatches, input_path: &Path) -> Result<ProgramOutput, Vec<Diagnostic>> {
+    let inputs = parse_input_values(matches).map_err(|e| vec![Diagnostic::error(e)])?;
+    let mut interpreter = InterpreterInstance::new(input_path).map_err(|e| vec![Diagnostic::error(e)])?
+        .with_search_paths(parse_search_paths(matches))
+        .with_prelude(!matches.is_present("NO_PRELUDE"));
+    interpreter.run(&inputs)
+}
+
+fn exec(matches: &ArgMatches, bytecode_path: &Path) -> Result<ProgramOutput, Vec<Diagnostic>> {
+    let inputs = parse_input_values(matches).map_err(|e| vec![Diagnostic::error(e)])?;
+    let mut vm = VirtualMachineInstance::new(bytecode_path).map_err(|e| vec![Diagnostic::error(e)])?;
+    vm.run(&inputs).map_err(|e| vec![Diagnostic::error(e)])
+}
+
+//Returns whether the file was already formatted
+fn format(matches: &ArgMatches, input_path: &Path) -> Result<bool, Vec<Diagnostic>> {
+    let code = fs::read_to_string(input_path).map_err(|e| vec![Diagnostic::error(format!("{}", e))])?;
+    let formatted = format_program(&code)?;
+    //Never overwrite the source with code that does not parse anymore
+    //The errors refer to the formatted code, so only their messages are shown
+    if let Err(errors) = format_program(&formatted) {
+        let diagnostic = errors.into_iter().fold(Diagnostic::error("The formatted code does not parse, the file was not changed"), |d, e| d.with_note(e.message));
+        return Err(vec![diagnostic]);
+    }
+    if formatted != code && !matches.is_present("CHECK") {
+        fs::write(input_path, &formatted).map_err(|e| vec![Diagnostic::error(format!("{}", e))])?;
+    }
+    Ok(formatted == code)
+}
+
 fn main() {
     let matches = parse_args();
-    match compile(matches) {
-        Ok(()) => println!("Compilation successful!"),
-        Err(e) => println!("Compilation failed: {}", e)
+    match matches.subcommand() {
+        ("run", Some(run_matches)) => {
+            let input_path = Path::new(run_matches.value_of("INPUT").unwrap());
+            match run(run_matches, input_path) {
+                Ok(output) => println!("{}", output),
+                Err(diagnostics) => {
+                    report(run_matches, input_path, &diagnostics);
+                    println!("Execution failed!");
+                    std::process::exit(1);
+                }
+            }
+        },
+        ("fmt", Some(fmt_matches)) => {
+            let input_path = Path::new(fmt_matches.value_of("INPUT").unwrap());
+            match format(fmt_matches, input_path) {
+                Ok(true) => (),
+                Ok(false) => if fmt_matches.is_present("CHECK") {
+                    println!("{} is not formatted", input_path.display());
+                    std::process::exit(1);
+                },
+                Err(diagnostics) => {
+                    report(fmt_matches, input_path, &diagnostics);
+                    println!("Formatting failed!");
+                    std::process::exit(1);
+                }
+            }
+        },
+        ("exec", Some(exec_matches)) => {
+            //Bytecode files have no source to show, so only the messages are printed
+            match exec(exec_matches, Path::new(exec_matches.value_of("INPUT").unwrap())) {
+                Ok(output) => println!("{}", output),
+                Err(diagnostics) => {
+                    for diagnostic in diagnostics {
+                        eprint!("{}", diagnostic.render("", ""));
+                    }
+                    println!("Execution failed!");
+                    std::process::exit(1);
+                }
+            }
+        },
+        _ => {
+            let input_path = Path::new(matches.value_of("INPUT").unwrap());
+            let json = matches.value_of("MESSAGE_FORMAT") == Some("json");
+            match compile(&matches, input_path) {
+                Ok(warnings) => {
+                    report(&matches, input_path, &warnings);
+                    if !json {
+                        println!("Compilation successful!")
+                    }
+                },
+                Err(diagnostics) => {
+                    report(&matches, input_path, &diagnostics);
+                    //Tools consuming JSON rely on the exit code instead of the message
+                    if !json {
+                        println!("Compilation failed!");
+                    }
+                    std::process::exit(1);
+                }
+            }
+        }
     }
 }
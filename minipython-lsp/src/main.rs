@@ -0,0 +1,35 @@
+mod transport;
+mod server;
+
+use server::Server;
+use std::io::{stdin, stdout};
+
+fn main() {
+    let stdin = stdin();
+    let stdout = stdout();
+    let mut input = stdin.lock();
+    let mut output = stdout.lock();
+    let mut server = Server::new();
+
+    loop {
+        let message = match transport::read_message(&mut input) {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("minipython-lsp: {}", e);
+                continue;
+            }
+        };
+
+        if message.get("method").and_then(|m| m.as_str()) == Some("exit") {
+            std::process::exit(if server.is_shut_down() { 0 } else { 1 });
+        }
+
+        for response in server.handle(&message) {
+            if let Err(e) = transport::write_message(&mut output, &response) {
+                eprintln!("minipython-lsp: {}", e);
+                return;
+            }
+        }
+    }
+}
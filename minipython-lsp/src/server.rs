@@ -0,0 +1,275 @@
+use minipython::analysis::{analyze_file, Analysis, Span};
+use minipython::compiler::{Diagnostic, Severity};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+//LSP positions are zero-based, while spans start at line and column 1. Default spans are 0, they end up at the start.
+fn span_to_range(span: Span) -> Value {
+    json!({
+        "start": { "line": span.start.line.saturating_sub(1), "character": span.start.col.saturating_sub(1) },
+        "end": { "line": span.end.line.saturating_sub(1), "character": span.end.col.saturating_sub(1) }
+    })
+}
+
+fn position(params: &Value) -> Option<(usize, usize)> {
+    let position = params.get("position")?;
+    let line = position.get("line")?.as_u64()? as usize;
+    let character = position.get("character")?.as_u64()? as usize;
+    Some((line + 1, character + 1))
+}
+
+fn document_uri(params: &Value) -> Option<&str> {
+    params.get("textDocument")?.get("uri")?.as_str()
+}
+
+//Imports are resolved next to the file, which requires a local path
+fn uri_to_path(uri: &str) -> Option<&Path> {
+    uri.strip_prefix("file://").map(Path::new)
+}
+
+fn signature(name: &str, parameters: &[String]) -> String {
+    format!("def {}({})", name, parameters.join(", "))
+}
+
+//Diagnostics in imported files are left out, they are shown when those files are opened
+fn diagnostic_to_json(uri: &str, diagnostic: &Diagnostic) -> Option<Value> {
+    if diagnostic.file.is_some() {
+        return None;
+    }
+    let primary = diagnostic.primary.as_ref()?;
+    let mut message = diagnostic.message.clone();
+    for note in &diagnostic.notes {
+        message.push_str(&format!("\nnote: {}", note));
+    }
+    let related: Vec<Value> = diagnostic.secondary.iter().map(|label| json!({
+        "location": { "uri": uri, "range": span_to_range(label.span) },
+        "message": label.message
+    })).collect();
+    Some(json!({
+        "range": span_to_range(primary.span),
+        "severity": match diagnostic.severity {
+            Severity::Error => 1,
+            Severity::Warning => 2
+        },
+        "code": diagnostic.code,
+        "source": "minipython",
+        "message": message,
+        "relatedInformation": related
+    }))
+}
+
+pub struct Server {
+    documents: HashMap<String, Analysis>,
+    shutdown: bool,
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server {
+            documents: HashMap::new(),
+            shutdown: false,
+        }
+    }
+
+    pub fn is_shut_down(&self) -> bool {
+        self.shutdown
+    }
+
+    //Returns the messages to send back: the response for requests, and notifications like published diagnostics
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message.get("method").and_then(Value::as_str).unwrap_or("");
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        match message.get("id") {
+            Some(id) => vec![match self.handle_request(method, &params) {
+                Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                Err((code, error)) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": error } })
+            }],
+            None => self.handle_notification(method, &params)
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+        match method {
+            "initialize" => Ok(json!({
+                "capabilities": {
+                    //Documents are always sent completely
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": {}
+                },
+                "serverInfo": { "name": "minipython-lsp" }
+            })),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Value::Null)
+            },
+            "textDocument/definition" => self.with_document(params, |uri, analysis, (line, col)| {
+                analysis.identifier_at(line, col)
+                    .and_then(|(name, _)| analysis.function(name))
+                    .map(|function| json!({ "uri": uri, "range": span_to_range(function.name_span) }))
+                    .unwrap_or(Value::Null)
+            }),
+            "textDocument/hover" => self.with_document(params, |_, analysis, (line, col)| {
+                match analysis.identifier_at(line, col) {
+                    Some((name, span)) => match analysis.function(name) {
+                        Some(function) => json!({
+                            "contents": {
+                                "kind": "markdown",
+                                "value": format!("```\n{}\n```\nTakes {} parameters", signature(&function.name, &function.parameters), function.parameters.len())
+                            },
+                            "range": span_to_range(span)
+                        }),
+                        None => Value::Null
+                    },
+                    None => Value::Null
+                }
+            }),
+            "textDocument/completion" => self.with_document(params, |_, analysis, (line, col)| {
+                let mut items: Vec<Value> = analysis.functions.iter().map(|function| json!({
+                    "label": function.name,
+                    //Function
+                    "kind": 3,
+                    "detail": signature(&function.name, &function.parameters)
+                })).collect();
+                if let Some(scope) = analysis.scope_at(line, col) {
+                    items.extend(scope.variables.iter().map(|variable| json!({
+                        "label": variable,
+                        //Variable
+                        "kind": 6
+                    })));
+                }
+                Value::Array(items)
+            }),
+            _ => Err((METHOD_NOT_FOUND, format!("Unknown method {}", method)))
+        }
+    }
+
+    fn with_document(&self, params: &Value, f: impl Fn(&str, &Analysis, (usize, usize)) -> Value) -> Result<Value, (i64, String)> {
+        let uri = document_uri(params).ok_or_else(|| (INVALID_PARAMS, String::from("Missing document")))?;
+        let position = position(params).ok_or_else(|| (INVALID_PARAMS, String::from("Missing position")))?;
+        match self.documents.get(uri) {
+            Some(analysis) => Ok(f(uri, analysis, position)),
+            None => Ok(Value::Null)
+        }
+    }
+
+    fn handle_notification(&mut self, method: &str, params: &Value) -> Vec<Value> {
+        let uri = match document_uri(params) {
+            Some(uri) => String::from(uri),
+            None => return Vec::new()
+        };
+        let text = match method {
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            "textDocument/didChange" => params["contentChanges"].as_array().and_then(|changes| changes.last()).and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            },
+            _ => None
+        };
+        match text {
+            Some(text) => {
+                let analysis = analyze_file(text, uri_to_path(&uri), &[]);
+                let diagnostics = analysis.diagnostics.iter().filter_map(|d| diagnostic_to_json(&uri, d)).collect();
+                self.documents.insert(uri.clone(), analysis);
+                vec![publish_diagnostics(&uri, diagnostics)]
+            },
+            None => Vec::new()
+        }
+    }
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Value>) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::{span_to_range, Server};
+    use minipython::analysis::Span;
+    use serde_json::{json, Value};
+
+    const URI: &str = "file:///test.mpy";
+
+    fn open(server: &mut Server, text: &str) -> Vec<Value> {
+        server.handle(&json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": URI, "languageId": "minipython", "version": 1, "text": text } }
+        }))
+    }
+
+    fn request(server: &mut Server, method: &str, line: usize, character: usize) -> Value {
+        let response = server.handle(&json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": { "textDocument": { "uri": URI }, "position": { "line": line, "character": character } }
+        }));
+        response[0]["result"].clone()
+    }
+
+    const CODE: &str =
+        "input: x
+output: y
+def add(a, b):
+    while a!=0:
+        a-=1
+        b+=1
+    return b
+y=add(x, x)";
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::new();
+        let messages = open(&mut server, &CODE.replace("add(x, x)", "add(x)"));
+        let diagnostics = &messages[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "E0005");
+        assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 7, "character": 0 }));
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let mut server = Server::new();
+        open(&mut server, CODE);
+        let definition = request(&mut server, "textDocument/definition", 7, 3);
+        assert_eq!(definition["range"]["start"], json!({ "line": 2, "character": 4 }));
+        let hover = request(&mut server, "textDocument/hover", 7, 3);
+        assert!(hover["contents"]["value"].as_str().unwrap().contains("Takes 2 parameters"));
+        assert_eq!(request(&mut server, "textDocument/hover", 7, 0), Value::Null);
+    }
+
+    #[test]
+    fn test_completion() {
+        let mut server = Server::new();
+        open(&mut server, CODE);
+        let labels = |items: Value| items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap().to_string()).collect::<Vec<String>>();
+        assert_eq!(labels(request(&mut server, "textDocument/completion", 4, 8)), vec!["add", "a", "b"]);
+        assert_eq!(labels(request(&mut server, "textDocument/completion", 7, 0)), vec!["add", "x", "y"]);
+    }
+
+    #[test]
+    fn test_unknown_method() {
+        let mut server = Server::new();
+        let response = server.handle(&json!({ "jsonrpc": "2.0", "id": 3, "method": "workspace/symbol", "params": {} }));
+        assert_eq!(response[0]["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn test_default_span() {
+        assert_eq!(span_to_range(Span::default()), json!({
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 0 }
+        }));
+    }
+}
@@ -0,0 +1,50 @@
+use serde_json::Value;
+use std::io::{BufRead, Write};
+
+//Messages are JSON-RPC objects preceded by HTTP-like headers, of which only Content-Length is needed
+pub fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, String> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        let read = input.read_line(&mut line).map_err(|e| format!("{}", e))?;
+        if read == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            content_length = Some(value.trim().parse::<usize>().map_err(|_| format!("Invalid header: {}", line))?);
+        }
+    }
+
+    let length = content_length.ok_or_else(|| String::from("Message without Content-Length header"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content).map_err(|e| format!("{}", e))?;
+    serde_json::from_slice(&content).map(Some).map_err(|e| format!("Invalid message: {}", e))
+}
+
+pub fn write_message(output: &mut impl Write, message: &Value) -> Result<(), String> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content).map_err(|e| format!("{}", e))?;
+    output.flush().map_err(|e| format!("{}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transport::{read_message, write_message};
+    use serde_json::json;
+
+    #[test]
+    fn test_roundtrip() {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": "shutdown" });
+        let mut bytes = Vec::new();
+        write_message(&mut bytes, &message).unwrap();
+        write_message(&mut bytes, &message).unwrap();
+        let mut input = &bytes[..];
+        assert_eq!(read_message(&mut input), Ok(Some(message.clone())));
+        assert_eq!(read_message(&mut input), Ok(Some(message)));
+        assert_eq!(read_message(&mut input), Ok(None));
+    }
+}
@@ -0,0 +1,280 @@
+use crate::parser;
+use crate::ir;
+use crate::warnings;
+use crate::resolver::Resolver;
+use crate::codegen::OverflowPolicy;
+use crate::ast::{Ast, Module, Program};
+use crate::name::{NameStore, InternedName};
+use crate::lexer::{Lexer, Token};
+use crate::diagnostic::Diagnostic;
+use std::path::{Path, PathBuf};
+
+pub use crate::lexer::{Span, Location};
+pub use crate::warnings::DeadCode;
+
+//Information about a source file for editor support. Everything is based on the recovered program,
+//so it is available even if the file contains errors.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub parameters: Vec<String>,
+    pub span: Span,
+    pub name_span: Span,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Scope {
+    //None for the top level
+    pub function: Option<String>,
+    //Function scopes reach until the next top-level statement, so positions below the body are included
+    pub span: Span,
+    pub variables: Vec<String>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Analysis {
+    pub diagnostics: Vec<Diagnostic>,
+    pub functions: Vec<FunctionInfo>,
+    pub scopes: Vec<Scope>,
+    //Empty if the program contains errors
+    pub dead_code: DeadCode,
+    identifiers: Vec<(String, Span)>,
+}
+
+fn before(a: (usize, usize), b: Location) -> bool {
+    a < (b.line, b.col)
+}
+
+fn after(a: (usize, usize), b: Location) -> bool {
+    a >= (b.line, b.col)
+}
+
+fn push_name(variables: &mut Vec<String>, name_store: &NameStore, name: InternedName) {
+    let name = name_store.get(name).unwrap();
+    if !variables.contains(name) {
+        variables.push(name.clone());
+    }
+}
+
+fn collect_variables(body: &[Ast], name_store: &NameStore, variables: &mut Vec<String>) {
+    for statement in body {
+        match statement {
+            Ast::Incr(name, _) | Ast::Decr(name, _) | Ast::Return(name, _) => push_name(variables, name_store, *name),
+            Ast::While { cond_var, body, .. } => {
+                push_name(variables, name_store, *cond_var);
+                collect_variables(body, name_store, variables);
+            },
+            Ast::Assign { var_name, args, .. } => {
+                push_name(variables, name_store, *var_name);
+                for &arg in args {
+                    push_name(variables, name_store, arg);
+                }
+            },
+            Ast::Def { .. } => ()
+        }
+    }
+}
+
+fn end_of_source(source: &str) -> Location {
+    Location {
+        line: source.lines().count() + 1,
+        col: 1,
+        pos: source.chars().count(),
+    }
+}
+
+pub fn analyze(source: &str) -> Analysis {
+    analyze_file(source, None, &[])
+}
+
+//Imports are looked up next to the file and in the search paths. Diagnostics in imported files have their file set.
+pub fn analyze_file(source: &str, file: Option<&Path>, search_paths: &[PathBuf]) -> Analysis {
+    let is_module = parser::is_module(source);
+    //Modules are analyzed like programs without inputs and main block
+    let (mut name_store, program, mut diagnostics) = if is_module {
+        let (mut name_store, module, diagnostics) = parser::parse_module_with_recovery(source);
+        let output = name_store.register("output");
+        let program = module.map(|module| Program {
+            imports: module.imports,
+            body: module.functions,
+            inputs: Vec::new(),
+            output,
+            imported: Vec::new(),
+        });
+        (name_store, program, diagnostics)
+    } else {
+        parser::parse_program_with_recovery(source)
+    };
+    let mut dead_code = DeadCode::default();
+
+    //Semantic errors are only reported for programs without syntax errors, as skipped statements cause false positives
+    if let Some(program) = &program {
+        if diagnostics.is_empty() {
+            let mut resolver = Resolver::new(search_paths);
+            if is_module {
+                //Warnings are not reported for modules, as they are about the program using the functions
+                let module = Module { imports: program.imports.clone(), functions: program.body.clone() };
+                if let Err(errors) = resolver.check_module(module, &mut name_store, file) {
+                    diagnostics.extend(errors);
+                }
+            } else {
+                let mut resolved = program.clone();
+                let ir = resolver.resolve_program(&mut resolved, &mut name_store, file)
+                    .and_then(|_| ir::convert_program_to_ir(&resolved, &name_store, ir::DecrementPolicy::Wrap, OverflowPolicy::Wrap));
+                match ir {
+                    Ok(ir) => {
+                        diagnostics.extend(warnings::check_program(&ir, &name_store));
+                        dead_code = warnings::find_dead_code(&ir, &name_store);
+                    },
+                    Err(errors) => diagnostics.extend(errors)
+                }
+            }
+        }
+    }
+
+    let identifiers: Vec<(String, Span)> = Lexer::new(source)
+        .filter_map(Result::ok)
+        .filter_map(|(l, token, r)| match token {
+            Token::Name(name) => Some((String::from(name), Span::new(l, r))),
+            _ => None
+        })
+        .collect();
+
+    let mut functions = Vec::new();
+    let mut scopes = Vec::new();
+    if let Some(program) = &program {
+        let mut top_level = Vec::new();
+        if !is_module {
+            for &name in program.inputs.iter().chain(std::iter::once(&program.output)) {
+                push_name(&mut top_level, &name_store, name);
+            }
+        }
+        collect_variables(&program.body, &name_store, &mut top_level);
+        scopes.push(Scope {
+            function: None,
+            span: Span::new(Location::default(), end_of_source(source)),
+            variables: top_level,
+        });
+
+        for (i, statement) in program.body.iter().enumerate() {
+            if let Ast::Def { name, parameters, body, span } = statement {
+                let name = name_store.get(*name).unwrap().clone();
+                let parameters: Vec<String> = parameters.iter().map(|&p| name_store.get(p).unwrap().clone()).collect();
+                let mut variables = parameters.clone();
+                collect_variables(body, &name_store, &mut variables);
+                let scope_end = program.body.get(i + 1).map(|next| next.span().start).unwrap_or_else(|| end_of_source(source));
+                //The name is the first identifier after the def keyword
+                let name_span = identifiers.iter()
+                    .find(|(_, s)| s.start.pos > span.start.pos)
+                    .map(|(_, s)| *s)
+                    .unwrap_or(*span);
+                functions.push(FunctionInfo {
+                    name: name.clone(),
+                    parameters,
+                    span: *span,
+                    name_span,
+                });
+                scopes.push(Scope {
+                    function: Some(name),
+                    span: Span::new(span.start, scope_end),
+                    variables,
+                });
+            }
+        }
+    }
+
+    Analysis {
+        diagnostics,
+        functions,
+        scopes,
+        dead_code,
+        identifiers,
+    }
+}
+
+//Lines and columns start at 1, like in diagnostics
+impl Analysis {
+    pub fn function(&self, name: &str) -> Option<&FunctionInfo> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    //A position directly after an identifier still refers to it, as that is where the cursor is after typing it
+    pub fn identifier_at(&self, line: usize, col: usize) -> Option<(&str, Span)> {
+        self.identifiers.iter()
+            .find(|(_, span)| after((line, col), span.start) && !after((line, col), Location { col: span.end.col + 1, ..span.end }))
+            .map(|(name, span)| (name.as_str(), *span))
+    }
+
+    pub fn scope_at(&self, line: usize, col: usize) -> Option<&Scope> {
+        self.scopes.iter()
+            .rev()
+            .find(|scope| after((line, col), scope.span.start) && before((line, col), scope.span.end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::analysis::analyze;
+
+    const CODE: &str =
+        "input: x
+output: y
+def add(a, b):
+    while a!=0:
+        a-=1
+        b+=1
+    return b
+
+y=add(x, x)
+z+=1";
+
+    #[test]
+    fn test_functions_and_scopes() {
+        let analysis = analyze(CODE);
+        let diagnostics: Vec<String> = analysis.diagnostics.iter().map(|d| format!("{}", d)).collect();
+        assert_eq!(diagnostics, vec!["10:1: warning[W0001]: Variable z is assigned but never read"]);
+        let add = analysis.function("add").unwrap();
+        assert_eq!(add.parameters, vec!["a", "b"]);
+        assert_eq!((add.name_span.start.line, add.name_span.start.col), (3, 5));
+
+        let function_scope = analysis.scope_at(7, 1).unwrap();
+        assert_eq!(function_scope.function.as_deref(), Some("add"));
+        assert_eq!(function_scope.variables, vec!["a", "b"]);
+        let top_level = analysis.scope_at(10, 3).unwrap();
+        assert_eq!(top_level.function, None);
+        assert_eq!(top_level.variables, vec!["x", "y", "z"]);
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let analysis = analyze(&CODE.replace("y=add(x, x)", "y+=1"));
+        assert_eq!(analysis.dead_code.unused_functions.len(), 1);
+        assert_eq!(analysis.dead_code.unused_functions[0].0, "add");
+        assert!(analysis.dead_code.unreachable.is_empty());
+    }
+
+    #[test]
+    fn test_module() {
+        let analysis = analyze("def add(a, b):\n    return a\ndef twice(a):\n    r=add(a)\n    return r\n");
+        let codes: Vec<Option<&str>> = analysis.diagnostics.iter().map(|d| d.code).collect();
+        assert_eq!(codes, vec![Some("E0005")]);
+        assert_eq!(analysis.functions.len(), 2);
+        assert!(analysis.scopes[0].variables.is_empty());
+    }
+
+    #[test]
+    fn test_identifier_at() {
+        let analysis = analyze(CODE);
+        assert_eq!(analysis.identifier_at(9, 3).map(|(name, _)| name), Some("add"));
+        assert_eq!(analysis.identifier_at(9, 6).map(|(name, _)| name), Some("add"));
+        assert_eq!(analysis.identifier_at(4, 1), None);
+    }
+
+    #[test]
+    fn test_diagnostics_with_syntax_errors() {
+        //Both parentheses are unexpected
+        let analysis = analyze(&CODE.replace("z+=1", "z+=1\nq=(x)"));
+        assert_eq!(analysis.diagnostics.len(), 2);
+        assert!(analysis.function("add").is_some());
+    }
+}
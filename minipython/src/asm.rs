@@ -0,0 +1,429 @@
+use crate::ir::*;
+use crate::name::*;
+use std::error::Error;
+use std::io::Write;
+use std::collections::HashMap;
+use crate::value::Value;
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+
+//Callee-saved registers, so values survive function calls without spilling
+const VALUE_REGISTERS: [&str; 5] = ["%rbx", "%r12", "%r13", "%r14", "%r15"];
+const ARG_REGISTERS: [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+
+//Minimal runtime using Linux system calls, so no libc is needed
+const RUNTIME: &str = "__mp_write_string:
+    movq %rsi, %rdx
+    movq %rdi, %rsi
+    movl $1, %edi
+    movl $1, %eax
+    syscall
+    ret
+__mp_write_value:
+    subq $40, %rsp
+    movq %rdi, %rax
+    leaq 32(%rsp), %rsi
+    movl $10, %ecx
+.Lwrite_digit:
+    xorl %edx, %edx
+    divq %rcx
+    addb $48, %dl
+    decq %rsi
+    movb %dl, (%rsi)
+    testq %rax, %rax
+    jnz .Lwrite_digit
+    movq %rsi, %rdi
+    leaq 32(%rsp), %rdx
+    subq %rsi, %rdx
+    movq %rdx, %rsi
+    call __mp_write_string
+    addq $40, %rsp
+    ret
+__mp_read_value:
+    pushq %rbx
+    pushq %r12
+    subq $24, %rsp
+    xorl %ebx, %ebx
+    xorl %r12d, %r12d
+.Lread_char:
+    xorl %eax, %eax
+    xorl %edi, %edi
+    movq %rsp, %rsi
+    movl $1, %edx
+    syscall
+    cmpq $1, %rax
+    jne .Lread_done
+    movzbl (%rsp), %eax
+    subl $48, %eax
+    cmpl $9, %eax
+    ja .Lread_other
+    imulq $10, %rbx
+    addq %rax, %rbx
+    movl $1, %r12d
+    jmp .Lread_char
+.Lread_other:
+    testl %r12d, %r12d
+    jnz .Lread_done
+    addl $48, %eax
+    cmpl $32, %eax
+    jbe .Lread_char
+.Lread_done:
+    movq %rbx, %rax
+    addq $24, %rsp
+    popq %r12
+    popq %rbx
+    ret
+";
+
+struct FunctionLayout {
+    locations: HashMap<Value, String>,
+    saved_registers: Vec<&'static str>,
+    stack_size: usize,
+    return_label: String,
+}
+
+impl FunctionLayout {
+    fn location(&self, v: Value) -> &str {
+        &self.locations[&v]
+    }
+}
+
+fn add_use(uses: &mut HashMap<Value, u64>, v: Value, weight: u64) {
+    let count = uses.entry(v).or_insert(0);
+    *count = count.saturating_add(weight);
+}
+
+fn collect_uses(statements: &[IRStatement], weight: u64, uses: &mut HashMap<Value, u64>) {
+    for statement in statements {
+        match statement {
+            ValueModify(val, _, _) => add_use(uses, *val, weight),
+            Return(val, _) => add_use(uses, *val, weight),
+            FunctionCall { args, target, .. } => {
+                for &arg in args {
+                    add_use(uses, arg, weight);
+                }
+                add_use(uses, *target, weight);
+            },
+            Loop { condition_var, body, .. } => {
+                add_use(uses, *condition_var, weight);
+                collect_uses(body, weight.saturating_mul(10), uses);
+            }
+        }
+    }
+}
+
+//Values that are used most often (weighted by loop depth) get registers, everything else lives on the stack
+fn allocate_values(values: &[Value], body: &[IRStatement], return_label: String) -> FunctionLayout {
+    let mut uses = HashMap::new();
+    collect_uses(body, 1, &mut uses);
+    let mut sorted = values.to_vec();
+    sorted.sort_by_key(|v| std::cmp::Reverse(uses.get(v).copied().unwrap_or(0)));
+
+    let saved_count = sorted.len().min(VALUE_REGISTERS.len());
+    let spilled = sorted.len() - saved_count;
+    let mut locations = HashMap::new();
+
+    for (i, &val) in sorted.iter().enumerate() {
+        let location = if i < saved_count {
+            String::from(VALUE_REGISTERS[i])
+        } else {
+            //Spilled values are placed below the saved registers
+            format!("-{}(%rbp)", 8 * (i + 1))
+        };
+        locations.insert(val, location);
+    }
+
+    //Keep the stack 16-byte aligned for calls
+    let stack_size = if (saved_count + spilled).is_multiple_of(2) { 8 * spilled } else { 8 * spilled + 8 };
+
+    FunctionLayout {
+        locations,
+        saved_registers: VALUE_REGISTERS[..saved_count].to_vec(),
+        stack_size,
+        return_label,
+    }
+}
+
+fn compile_statement(statement: &IRStatement, name_store: &NameStore, layout: &FunctionLayout, next_label: &mut u64, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    match statement {
+        ValueModify(val, change, _) => {
+            let location = layout.location(*val);
+            if *change >= i64::from(i32::MIN) && *change <= i64::from(i32::MAX) {
+                writeln!(output, "    addq ${}, {}", change, location)?;
+            } else {
+                writeln!(output, "    movabsq ${}, %rax", change)?;
+                writeln!(output, "    addq %rax, {}", location)?;
+            }
+        },
+        Return(val, _) => {
+            writeln!(output, "    movq {}, %rax", layout.location(*val))?;
+            writeln!(output, "    jmp {}", layout.return_label)?;
+        },
+        Loop { condition_var, body, .. } => {
+            let id = *next_label;
+            *next_label += 1;
+            writeln!(output, ".Lloop_{}:", id)?;
+            writeln!(output, "    cmpq $0, {}", layout.location(*condition_var))?;
+            writeln!(output, "    je .Lloop_end_{}", id)?;
+
+            for st in body {
+                compile_statement(st, name_store, layout, next_label, output)?;
+            }
+
+            writeln!(output, "    jmp .Lloop_{}", id)?;
+            writeln!(output, ".Lloop_end_{}:", id)?;
+        },
+        FunctionCall { func, args, target, .. } => {
+            let stack_args = args.len().saturating_sub(ARG_REGISTERS.len());
+            let padding = if stack_args.is_multiple_of(2) { 0 } else { 8 };
+            if padding != 0 {
+                writeln!(output, "    subq ${}, %rsp", padding)?;
+            }
+            for &arg in args.iter().skip(ARG_REGISTERS.len()).rev() {
+                writeln!(output, "    pushq {}", layout.location(arg))?;
+            }
+            for (&arg, reg) in args.iter().zip(ARG_REGISTERS.iter()) {
+                writeln!(output, "    movq {}, {}", layout.location(arg), reg)?;
+            }
+            writeln!(output, "    call {}", to_function_name(*func, name_store))?;
+            if stack_args > 0 {
+                writeln!(output, "    addq ${}, %rsp", 8 * stack_args + padding)?;
+            }
+            writeln!(output, "    movq %rax, {}", layout.location(*target))?;
+        }
+    }
+    Ok(())
+}
+
+//Functions are global symbols, the prefix keeps them apart from _start and the runtime helpers
+fn to_function_name(name: InternedName, name_store: &NameStore) -> String {
+    format!("mp_{}", name_store.get(name).unwrap())
+}
+
+fn compile_block(block: &IRBlock, name_store: &NameStore, layout: &FunctionLayout, next_label: &mut u64, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    for &val in &block.values {
+        writeln!(output, "    movq $0, {}", layout.location(val))?;
+    }
+
+    for statement in &block.body {
+        compile_statement(statement, name_store, layout, next_label, output)?;
+    }
+
+    Ok(())
+}
+
+fn compile_function(function_name: &str, function: &IRFunction, name_store: &NameStore, next_label: &mut u64, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let mut values = function.params.clone();
+    values.extend(&function.body.values);
+    let layout = allocate_values(&values, &function.body.body, format!(".Lreturn_{}", function_name));
+
+    writeln!(output, "    .globl {}", function_name)?;
+    writeln!(output, "{}:", function_name)?;
+    writeln!(output, "    pushq %rbp")?;
+    writeln!(output, "    movq %rsp, %rbp")?;
+    for reg in &layout.saved_registers {
+        writeln!(output, "    pushq {}", reg)?;
+    }
+    if layout.stack_size > 0 {
+        writeln!(output, "    subq ${}, %rsp", layout.stack_size)?;
+    }
+
+    for (i, &param) in function.params.iter().enumerate() {
+        match ARG_REGISTERS.get(i) {
+            Some(reg) => writeln!(output, "    movq {}, {}", reg, layout.location(param))?,
+            None => {
+                writeln!(output, "    movq {}(%rbp), %rax", 16 + 8 * (i - ARG_REGISTERS.len()))?;
+                writeln!(output, "    movq %rax, {}", layout.location(param))?;
+            }
+        }
+    }
+
+    compile_block(&function.body, name_store, &layout, next_label, output)?;
+
+    writeln!(output, "{}:", layout.return_label)?;
+    writeln!(output, "    leaq -{}(%rbp), %rsp", 8 * layout.saved_registers.len())?;
+    for reg in layout.saved_registers.iter().rev() {
+        writeln!(output, "    popq {}", reg)?;
+    }
+    writeln!(output, "    popq %rbp")?;
+    writeln!(output, "    ret")?;
+    Ok(())
+}
+
+fn write_string(output: &mut dyn Write, label: &str, len: usize) -> Result<(), Box<dyn Error>> {
+    writeln!(output, "    leaq {}(%rip), %rdi", label)?;
+    writeln!(output, "    movl ${}, %esi", len)?;
+    writeln!(output, "    call __mp_write_string")?;
+    Ok(())
+}
+
+pub fn compile_to_asm(program: &IRProgram, name_store: &NameStore, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let mut prompts = Vec::new();
+    for &input_val in &program.inputs {
+        prompts.push(format!("{}=", input_val.get_name(name_store).unwrap()));
+    }
+    let output_prompt = format!("{}=", program.output.get_name(name_store).unwrap());
+
+    writeln!(output, "    .section .rodata")?;
+    for (i, prompt) in prompts.iter().enumerate() {
+        writeln!(output, "__mp_prompt_{}:", i)?;
+        writeln!(output, "    .ascii \"{}\"", prompt)?;
+    }
+    writeln!(output, "__mp_output_prompt:")?;
+    writeln!(output, "    .ascii \"{}\"", output_prompt)?;
+
+    writeln!(output, "    .text")?;
+    write!(output, "{}", RUNTIME)?;
+
+    let mut next_label = 0;
+
+    for (function_name, function) in &program.functions {
+        compile_function(&to_function_name(*function_name, name_store), function, name_store, &mut next_label, output)?;
+    }
+
+    let mut values = program.inputs.clone();
+    values.push(program.output);
+    values.extend(&program.main.values);
+    let layout = allocate_values(&values, &program.main.body, String::from(".Lexit"));
+
+    writeln!(output, "    .globl _start")?;
+    writeln!(output, "_start:")?;
+    writeln!(output, "    movq %rsp, %rbp")?;
+    //_start never returns, so the registers do not need to be saved, only the stack space has to be reserved
+    let frame_size = 8 * layout.saved_registers.len() + layout.stack_size;
+    if frame_size > 0 {
+        writeln!(output, "    subq ${}, %rsp", frame_size)?;
+    }
+
+    for (i, &input_val) in program.inputs.iter().enumerate() {
+        write_string(output, &format!("__mp_prompt_{}", i), prompts[i].len())?;
+        writeln!(output, "    call __mp_read_value")?;
+        writeln!(output, "    movq %rax, {}", layout.location(input_val))?;
+    }
+
+    writeln!(output, "    movq $0, {}", layout.location(program.output))?;
+
+    compile_block(&program.main, name_store, &layout, &mut next_label, output)?;
+
+    write_string(output, "__mp_output_prompt", output_prompt.len())?;
+    writeln!(output, "    movq {}, %rdi", layout.location(program.output))?;
+    writeln!(output, "    call __mp_write_value")?;
+    writeln!(output, "{}:", layout.return_label)?;
+    writeln!(output, "    movl $60, %eax")?;
+    writeln!(output, "    xorl %edi, %edi")?;
+    writeln!(output, "    syscall")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::CodegenOptions;
+    use crate::compiler::{compile_to_string, CheckOptions, Target};
+    use std::process::{Command, Stdio};
+    use std::io::Write;
+    use std::fs;
+
+    const CODE: &str =
+        "input: x
+output: y
+def f(a):
+    while a!=0:
+        a-=1
+        return a
+    #endwhile
+    return a
+#enddef
+y=f(x)
+y+=1
+";
+
+    //Only the compiled program, the I/O helpers before it are the same for every program
+    #[test]
+    fn test_asm_golden() {
+        let expected =
+            "    .globl mp_f
+mp_f:
+    pushq %rbp
+    movq %rsp, %rbp
+    pushq %rbx
+    subq $8, %rsp
+    movq %rdi, %rbx
+.Lloop_0:
+    cmpq $0, %rbx
+    je .Lloop_end_0
+    addq $-1, %rbx
+    movq %rbx, %rax
+    jmp .Lreturn_mp_f
+    jmp .Lloop_0
+.Lloop_end_0:
+    movq %rbx, %rax
+    jmp .Lreturn_mp_f
+.Lreturn_mp_f:
+    leaq -8(%rbp), %rsp
+    popq %rbx
+    popq %rbp
+    ret
+    .globl _start
+_start:
+    movq %rsp, %rbp
+    subq $16, %rsp
+    leaq __mp_prompt_0(%rip), %rdi
+    movl $2, %esi
+    call __mp_write_string
+    call __mp_read_value
+    movq %rax, %r12
+    movq $0, %rbx
+    movq %r12, %rdi
+    call mp_f
+    movq %rax, %rbx
+    addq $1, %rbx
+    leaq __mp_output_prompt(%rip), %rdi
+    movl $2, %esi
+    call __mp_write_string
+    movq %rbx, %rdi
+    call __mp_write_value
+.Lexit:
+    movl $60, %eax
+    xorl %edi, %edi
+    syscall
+";
+        let (asm, _) = compile_to_string(CODE, Target::Asm, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        let program = &asm[asm.find("    .globl mp_f").unwrap()..];
+        assert_eq!(program, expected);
+    }
+
+    //Assembles and links the program and runs it with each input, panics if as and ld are not installed
+    fn run_asm(name: &str, code: &str, runs: &[(&str, &str)]) {
+        let (asm, _) = compile_to_string(code, Target::Asm, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        let dir = std::env::temp_dir().join(format!("minipython-asm-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.s"), asm).unwrap();
+        let assembled = Command::new("as").arg("-o").arg(dir.join("main.o")).arg(dir.join("main.s")).output()
+            .expect("as is required to run the assembly tests");
+        assert!(assembled.status.success(), "as failed: {}", String::from_utf8_lossy(&assembled.stderr));
+        let linked = Command::new("ld").arg("-o").arg(dir.join("main")).arg(dir.join("main.o")).output()
+            .expect("ld is required to run the assembly tests");
+        assert!(linked.status.success(), "ld failed: {}", String::from_utf8_lossy(&linked.stderr));
+
+        for (input, expected) in runs {
+            let mut child = Command::new(dir.join("main")).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+            child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+            let output = child.wait_with_output().unwrap();
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), *expected);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_asm_run() {
+        run_asm("run", CODE, &[("0\n", "x=y=1"), ("5\n", "x=y=5")]);
+    }
+
+    #[test]
+    fn test_asm_function_names() {
+        //The names of the entry point and of runtime helpers
+        let code = "input: x\noutput: y\ndef _start(a):\n    a+=1\n    return a\n#enddef\ndef __mp_write_value(a):\n    a=_start(a)\n    return a\n#enddef\ny=__mp_write_value(x)\n";
+        run_asm("names", code, &[("5\n", "x=y=6")]);
+    }
+}
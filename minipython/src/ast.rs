@@ -1,18 +1,66 @@
 use crate::name::InternedName;
+use crate::lexer::Span;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub enum Ast {
-    Def { name: InternedName, parameters: Vec<InternedName>, body: Vec<Ast> },
-    Return(InternedName),
-    While { cond_var: InternedName, body: Vec<Ast> },
-    Assign { var_name: InternedName, fun_name: InternedName, args: Vec<InternedName> },
-    Incr(InternedName),
-    Decr(InternedName)
+    Def { name: InternedName, parameters: Vec<InternedName>, body: Vec<Ast>, span: Span },
+    Return(InternedName, Span),
+    While { cond_var: InternedName, body: Vec<Ast>, span: Span },
+    Assign { var_name: InternedName, fun_name: InternedName, args: Vec<InternedName>, span: Span },
+    Incr(InternedName, Span),
+    Decr(InternedName, Span)
+}
+
+impl Ast {
+    pub fn span(&self) -> Span {
+        match self {
+            Ast::Def { span, .. } => *span,
+            Ast::Return(_, span) => *span,
+            Ast::While { span, .. } => *span,
+            Ast::Assign { span, .. } => *span,
+            Ast::Incr(_, span) => *span,
+            Ast::Decr(_, span) => *span
+        }
+    }
+
+    //Makes comparing trees in tests easier
+    #[cfg(test)]
+    pub fn without_spans(&self) -> Ast {
+        let strip = |body: &Vec<Ast>| body.iter().map(Ast::without_spans).collect();
+        match self {
+            Ast::Def { name, parameters, body, .. } => Ast::Def { name: *name, parameters: parameters.clone(), body: strip(body), span: Span::default() },
+            Ast::Return(name, _) => Ast::Return(*name, Span::default()),
+            Ast::While { cond_var, body, .. } => Ast::While { cond_var: *cond_var, body: strip(body), span: Span::default() },
+            Ast::Assign { var_name, fun_name, args, .. } => Ast::Assign { var_name: *var_name, fun_name: *fun_name, args: args.clone(), span: Span::default() },
+            Ast::Incr(name, _) => Ast::Incr(*name, Span::default()),
+            Ast::Decr(name, _) => Ast::Decr(*name, Span::default())
+        }
+    }
+}
+
+//`import module` or `from module import f, g`
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Import {
+    pub module: InternedName,
+    //None imports every function of the module
+    pub functions: Option<Vec<InternedName>>,
+    pub span: Span,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Program {
+    //Removed by the resolver, which adds the imported functions to the body
+    pub imports: Vec<Import>,
     pub body: Vec<Ast>,
     pub inputs: Vec<InternedName>,
-    pub output: InternedName
+    pub output: InternedName,
+    //Functions in the body that were defined in other files
+    pub imported: Vec<InternedName>,
+}
+
+//A file that only contains functions, for use with imports
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Module {
+    pub imports: Vec<Import>,
+    pub functions: Vec<Ast>,
 }
\ No newline at end of file
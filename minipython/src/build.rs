@@ -0,0 +1,184 @@
+use crate::compiler::{render_diagnostics, CheckOptions, CodegenOptions, CompilerInstance, Target};
+use crate::diagnostic::Diagnostic;
+use crate::parser;
+use std::path::{Path, PathBuf};
+use std::env;
+use std::fs;
+
+//Compiles MiniPython sources from a build script, like lalrpop::process_root does for grammars:
+//
+//    fn main() {
+//        minipython::build::process_dir("src").unwrap();
+//    }
+#[derive(Debug, Clone)]
+pub struct Configuration {
+    in_dir: PathBuf,
+    out_dir: Option<PathBuf>,
+    target: Target,
+    options: CodegenOptions,
+    checks: CheckOptions,
+    search_paths: Vec<PathBuf>,
+    prelude: bool,
+}
+
+impl Default for Configuration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Configuration {
+    //Compiles src to Rust modules in OUT_DIR
+    pub fn new() -> Self {
+        Configuration {
+            in_dir: PathBuf::from("src"),
+            out_dir: None,
+            target: Target::Rust,
+            options: CodegenOptions::default(),
+            checks: CheckOptions::default(),
+            search_paths: Vec::new(),
+            prelude: true,
+        }
+    }
+
+    pub fn with_in_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.in_dir = dir.into();
+        self
+    }
+
+    pub fn with_out_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.out_dir = Some(dir.into());
+        self
+    }
+
+    pub fn with_target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn with_options(mut self, options: CodegenOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_checks(mut self, checks: CheckOptions) -> Self {
+        self.checks = checks;
+        self
+    }
+
+    //Modules are also found next to the importing file
+    pub fn with_search_path(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.search_paths.push(dir.into());
+        self
+    }
+
+    pub fn with_prelude(mut self, prelude: bool) -> Self {
+        self.prelude = prelude;
+        self
+    }
+
+    //Every .mpy file below the input directory is compiled to the same relative path in the output directory.
+    //Modules without input and output are only compiled as part of the programs importing them.
+    //Errors are printed to stderr, which cargo shows when the build script fails, and as cargo warnings. Returns the generated files.
+    pub fn process(&self) -> Result<Vec<PathBuf>, String> {
+        let out_dir = match &self.out_dir {
+            Some(dir) => dir.clone(),
+            None => env::var_os("OUT_DIR").map(PathBuf::from)
+                .ok_or_else(|| String::from("OUT_DIR is not set, an output directory is required outside of build scripts"))?
+        };
+
+        //Cargo checks the modification time of directories, so new files are picked up too
+        println!("cargo:rerun-if-changed={}", self.in_dir.display());
+        let mut sources = Vec::new();
+        collect_sources(&self.in_dir, &mut sources)?;
+        sources.sort();
+
+        let mut generated = Vec::new();
+        let mut failed = 0;
+        for source in &sources {
+            println!("cargo:rerun-if-changed={}", source.display());
+            let code = fs::read_to_string(source).map_err(|e| format!("Cannot read {}: {}", source.display(), e))?;
+            if parser::is_module(&code) {
+                continue;
+            }
+            let relative = source.strip_prefix(&self.in_dir).unwrap();
+            let output = out_dir.join(relative).with_extension(self.target.extension());
+            if let Some(parent) = output.parent() {
+                fs::create_dir_all(parent).map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+            }
+
+            let mut instance = CompilerInstance::new(source, &output, self.target, self.options, self.checks)?
+                .with_search_paths(self.search_paths.clone())
+                .with_prelude(self.prelude);
+            let result = instance.run();
+            //Modules can come from the search paths, which are outside of the input directory
+            for module in instance.module_files() {
+                println!("cargo:rerun-if-changed={}", module.display());
+            }
+            match result {
+                Ok(warnings) => {
+                    for warning in &warnings {
+                        println!("cargo:warning={}", cargo_warning(source, warning));
+                    }
+                    generated.push(output);
+                },
+                Err(diagnostics) => {
+                    //Cargo only shows stderr if the build script fails, the warnings are also shown in the build output
+                    for error in &diagnostics {
+                        println!("cargo:warning={}", cargo_warning(source, error));
+                    }
+                    eprintln!("{}", render_diagnostics(source, &diagnostics));
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed == 0 {
+            Ok(generated)
+        } else {
+            Err(format!("Failed to compile {} of {} MiniPython files", failed, sources.len()))
+        }
+    }
+}
+
+//Cargo warnings are a single line each, the full report is only printed for errors
+fn cargo_warning(source: &Path, diagnostic: &Diagnostic) -> String {
+    let file = diagnostic.file.as_deref().unwrap_or(source);
+    let separator = if diagnostic.primary.is_some() { ":" } else { ": " };
+    format!("{}{}{}", file.display(), separator, diagnostic)
+}
+
+fn collect_sources(dir: &Path, sources: &mut Vec<PathBuf>) -> Result<(), String> {
+    let entries = fs::read_dir(dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry.map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?.path();
+        if path.is_dir() {
+            collect_sources(&path, sources)?;
+        } else if path.extension().and_then(|e| e.to_str()) == Some("mpy") {
+            sources.push(path);
+        }
+    }
+    Ok(())
+}
+
+//Compiles every .mpy file below dir to a Rust module in OUT_DIR
+pub fn process_dir(dir: impl Into<PathBuf>) -> Result<Vec<PathBuf>, String> {
+    Configuration::new().with_in_dir(dir).process()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build::Configuration;
+    use crate::compiler::Target;
+    use std::fs;
+
+    #[test]
+    fn test_process() {
+        let out_dir = std::env::temp_dir().join(format!("minipython-build-{}", std::process::id()));
+        let generated = Configuration::new().with_in_dir("../examples").with_out_dir(&out_dir).with_target(Target::CLib).process().unwrap();
+        let names: Vec<String> = generated.iter().map(|p| p.file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, vec!["add.c", "div.c", "exp.c", "mul.c"]);
+        assert!(out_dir.join("exp.h").exists());
+        fs::remove_dir_all(&out_dir).unwrap();
+    }
+}
@@ -0,0 +1,351 @@
+use crate::ir::*;
+use crate::name::*;
+use std::error::Error;
+use std::io::Write;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use crate::value::Value;
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+
+pub const BYTECODE_MAGIC: &[u8; 4] = b"MPYC";
+pub const BYTECODE_VERSION: u16 = 1;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Instruction {
+    //Push the content of a local slot
+    Load(u32),
+    //Pop the top of the stack into a local slot
+    Store(u32),
+    Const(i64),
+    //Pop two values and push their (wrapping) sum
+    Add,
+    Jump(u32),
+    //Pop a value and jump if it is zero
+    JumpIfZero(u32),
+    //Pop the arguments of the called function and push its result
+    Call(u32),
+    Return,
+}
+
+const OP_LOAD: u8 = 0;
+const OP_STORE: u8 = 1;
+const OP_CONST: u8 = 2;
+const OP_ADD: u8 = 3;
+const OP_JUMP: u8 = 4;
+const OP_JUMP_IF_ZERO: u8 = 5;
+const OP_CALL: u8 = 6;
+const OP_RETURN: u8 = 7;
+
+#[derive(Debug, Eq, PartialEq)]
+pub struct BytecodeFunction {
+    pub name: String,
+    pub param_count: u32,
+    pub local_count: u32,
+    pub code: Vec<Instruction>,
+}
+
+//The main block is stored as a function taking the inputs as parameters and returning the output
+#[derive(Debug, Eq, PartialEq)]
+pub struct BytecodeProgram {
+    pub input_names: Vec<String>,
+    pub output_name: String,
+    pub functions: Vec<BytecodeFunction>,
+    pub main: BytecodeFunction,
+}
+
+struct FunctionCompiler<'a> {
+    slots: HashMap<Value, u32>,
+    function_indices: &'a HashMap<InternedName, u32>,
+    code: Vec<Instruction>,
+}
+
+impl<'a> FunctionCompiler<'a> {
+    fn new(values: &[Value], function_indices: &'a HashMap<InternedName, u32>) -> Self {
+        FunctionCompiler {
+            slots: values.iter().enumerate().map(|(i, &v)| (v, i as u32)).collect(),
+            function_indices,
+            code: Vec::new(),
+        }
+    }
+
+    fn slot(&self, v: Value) -> u32 {
+        self.slots[&v]
+    }
+
+    fn compile_statement(&mut self, statement: &IRStatement) {
+        match statement {
+            ValueModify(val, change, _) => {
+                let slot = self.slot(*val);
+                self.code.push(Instruction::Load(slot));
+                self.code.push(Instruction::Const(*change));
+                self.code.push(Instruction::Add);
+                self.code.push(Instruction::Store(slot));
+            },
+            Return(val, _) => {
+                self.code.push(Instruction::Load(self.slot(*val)));
+                self.code.push(Instruction::Return);
+            },
+            Loop { condition_var, body, .. } => {
+                let start = self.code.len() as u32;
+                self.code.push(Instruction::Load(self.slot(*condition_var)));
+                let exit_jump = self.code.len();
+                //The jump target is patched after the body was compiled
+                self.code.push(Instruction::JumpIfZero(0));
+
+                for st in body {
+                    self.compile_statement(st);
+                }
+
+                self.code.push(Instruction::Jump(start));
+                self.code[exit_jump] = Instruction::JumpIfZero(self.code.len() as u32);
+            },
+            FunctionCall { func, args, target, .. } => {
+                for &arg in args {
+                    self.code.push(Instruction::Load(self.slot(arg)));
+                }
+                self.code.push(Instruction::Call(self.function_indices[func]));
+                self.code.push(Instruction::Store(self.slot(*target)));
+            }
+        }
+    }
+
+    fn compile_block(mut self, block: &IRBlock) -> Vec<Instruction> {
+        for statement in &block.body {
+            self.compile_statement(statement);
+        }
+        self.code
+    }
+}
+
+pub fn generate_bytecode(program: &IRProgram, name_store: &NameStore) -> BytecodeProgram {
+    let function_indices: HashMap<InternedName, u32> = program.functions.iter().enumerate().map(|(i, (name, _))| (*name, i as u32)).collect();
+    let mut functions = Vec::new();
+
+    for (function_name, function) in &program.functions {
+        let mut values = function.params.clone();
+        values.extend(&function.body.values);
+        let code = FunctionCompiler::new(&values, &function_indices).compile_block(&function.body);
+        functions.push(BytecodeFunction {
+            name: name_store.get(*function_name).unwrap().clone(),
+            param_count: function.params.len() as u32,
+            local_count: values.len() as u32,
+            code,
+        });
+    }
+
+    let mut values = program.inputs.clone();
+    values.push(program.output);
+    values.extend(&program.main.values);
+    let mut compiler = FunctionCompiler::new(&values, &function_indices);
+    for statement in &program.main.body {
+        compiler.compile_statement(statement);
+    }
+    compiler.code.push(Instruction::Load(compiler.slot(program.output)));
+    compiler.code.push(Instruction::Return);
+
+    BytecodeProgram {
+        input_names: program.inputs.iter().map(|v| v.get_name(name_store).unwrap().clone()).collect(),
+        output_name: program.output.get_name(name_store).unwrap().clone(),
+        functions,
+        main: BytecodeFunction {
+            name: String::from("main"),
+            param_count: program.inputs.len() as u32,
+            local_count: values.len() as u32,
+            code: compiler.code,
+        },
+    }
+}
+
+fn write_u32(bytes: &mut Vec<u8>, v: u32) {
+    bytes.extend_from_slice(&v.to_le_bytes());
+}
+
+fn write_string(bytes: &mut Vec<u8>, s: &str) {
+    write_u32(bytes, s.len() as u32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn write_function(bytes: &mut Vec<u8>, function: &BytecodeFunction) {
+    write_string(bytes, &function.name);
+    write_u32(bytes, function.param_count);
+    write_u32(bytes, function.local_count);
+    write_u32(bytes, function.code.len() as u32);
+    for instr in &function.code {
+        match instr {
+            Instruction::Load(slot) => {
+                bytes.push(OP_LOAD);
+                write_u32(bytes, *slot);
+            },
+            Instruction::Store(slot) => {
+                bytes.push(OP_STORE);
+                write_u32(bytes, *slot);
+            },
+            Instruction::Const(c) => {
+                bytes.push(OP_CONST);
+                bytes.extend_from_slice(&c.to_le_bytes());
+            },
+            Instruction::Add => bytes.push(OP_ADD),
+            Instruction::Jump(target) => {
+                bytes.push(OP_JUMP);
+                write_u32(bytes, *target);
+            },
+            Instruction::JumpIfZero(target) => {
+                bytes.push(OP_JUMP_IF_ZERO);
+                write_u32(bytes, *target);
+            },
+            Instruction::Call(func) => {
+                bytes.push(OP_CALL);
+                write_u32(bytes, *func);
+            },
+            Instruction::Return => bytes.push(OP_RETURN)
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.pos + count > self.bytes.len() {
+            Err(String::from("Unexpected end of bytecode file"))
+        } else {
+            let slice = &self.bytes[self.pos..self.pos + count];
+            self.pos += count;
+            Ok(slice)
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| String::from("Invalid string in bytecode file"))
+    }
+
+    fn function(&mut self) -> Result<BytecodeFunction, String> {
+        let name = self.string()?;
+        let param_count = self.u32()?;
+        let local_count = self.u32()?;
+        let code_len = self.u32()?;
+        let mut code = Vec::new();
+        for _ in 0..code_len {
+            let instr = match self.u8()? {
+                OP_LOAD => Instruction::Load(self.u32()?),
+                OP_STORE => Instruction::Store(self.u32()?),
+                OP_CONST => Instruction::Const(self.i64()?),
+                OP_ADD => Instruction::Add,
+                OP_JUMP => Instruction::Jump(self.u32()?),
+                OP_JUMP_IF_ZERO => Instruction::JumpIfZero(self.u32()?),
+                OP_CALL => Instruction::Call(self.u32()?),
+                OP_RETURN => Instruction::Return,
+                op => return Err(format!("Unknown opcode {} in function {}", op, name))
+            };
+            code.push(instr);
+        }
+        Ok(BytecodeFunction {
+            name,
+            param_count,
+            local_count,
+            code,
+        })
+    }
+}
+
+//Checks that all slots, jump targets and function indices are in range, so the VM does not have to
+fn verify_function(function: &BytecodeFunction, functions: &[BytecodeFunction]) -> Result<(), String> {
+    if function.param_count > function.local_count {
+        return Err(format!("Function {} has more parameters than locals", function.name));
+    }
+    for instr in &function.code {
+        let valid = match instr {
+            Instruction::Load(slot) | Instruction::Store(slot) => *slot < function.local_count,
+            Instruction::Jump(target) | Instruction::JumpIfZero(target) => (*target as usize) <= function.code.len(),
+            Instruction::Call(func) => (*func as usize) < functions.len(),
+            Instruction::Const(_) | Instruction::Add | Instruction::Return => true
+        };
+        if !valid {
+            return Err(format!("Invalid instruction {:?} in function {}", instr, function.name));
+        }
+    }
+    Ok(())
+}
+
+impl BytecodeProgram {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(BYTECODE_MAGIC);
+        bytes.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+        write_u32(&mut bytes, self.input_names.len() as u32);
+        for name in &self.input_names {
+            write_string(&mut bytes, name);
+        }
+        write_string(&mut bytes, &self.output_name);
+        write_u32(&mut bytes, self.functions.len() as u32);
+        for function in &self.functions {
+            write_function(&mut bytes, function);
+        }
+        write_function(&mut bytes, &self.main);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<BytecodeProgram, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(BYTECODE_MAGIC.len()).ok() != Some(&BYTECODE_MAGIC[..]) {
+            return Err(String::from("Not a MiniPython bytecode file"));
+        }
+        let version = reader.u16()?;
+        if version != BYTECODE_VERSION {
+            return Err(format!("Unsupported bytecode version {} (expected {})", version, BYTECODE_VERSION));
+        }
+        let input_count = reader.u32()?;
+        let mut input_names = Vec::new();
+        for _ in 0..input_count {
+            input_names.push(reader.string()?);
+        }
+        let output_name = reader.string()?;
+        let function_count = reader.u32()?;
+        let mut functions = Vec::new();
+        for _ in 0..function_count {
+            functions.push(reader.function()?);
+        }
+        let main = reader.function()?;
+        if reader.pos != bytes.len() {
+            return Err(String::from("Unexpected data at the end of bytecode file"));
+        }
+        if main.param_count != input_count {
+            return Err(String::from("Main function does not match the program inputs"));
+        }
+        for function in functions.iter().chain(std::iter::once(&main)) {
+            verify_function(function, &functions)?;
+        }
+        Ok(BytecodeProgram {
+            input_names,
+            output_name,
+            functions,
+            main,
+        })
+    }
+}
+
+pub fn compile_to_bytecode(program: &IRProgram, name_store: &NameStore, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let bytecode = generate_bytecode(program, name_store);
+    output.write_all(&bytecode.to_bytes())?;
+    Ok(())
+}
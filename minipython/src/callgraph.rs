@@ -0,0 +1,185 @@
+use crate::ir::*;
+use crate::name::InternedName;
+use crate::lexer::Span;
+use crate::ir::IRStatement::{FunctionCall, Loop};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Call {
+    pub callee: InternedName,
+    pub span: Span,
+}
+
+pub struct CallGraph {
+    //In source order
+    functions: Vec<InternedName>,
+    calls: HashMap<InternedName, Vec<Call>>,
+    main_calls: Vec<Call>,
+}
+
+fn collect_calls(statements: &[IRStatement], calls: &mut Vec<Call>) {
+    for statement in statements {
+        match statement {
+            FunctionCall { func, span, .. } => calls.push(Call { callee: *func, span: *span }),
+            Loop { body, .. } => collect_calls(body, calls),
+            _ => ()
+        }
+    }
+}
+
+//Tarjan's algorithm for strongly connected components
+struct Components<'a> {
+    graph: &'a CallGraph,
+    indices: HashMap<InternedName, (usize, usize)>,
+    stack: Vec<InternedName>,
+    components: Vec<Vec<InternedName>>,
+}
+
+impl<'a> Components<'a> {
+    fn visit(&mut self, function: InternedName) {
+        let index = self.indices.len();
+        self.indices.insert(function, (index, index));
+        self.stack.push(function);
+
+        let graph = self.graph;
+        for call in graph.calls(function) {
+            let low = match self.indices.get(&call.callee) {
+                None => {
+                    self.visit(call.callee);
+                    self.indices[&call.callee].1
+                },
+                Some(&(callee_index, _)) if self.stack.contains(&call.callee) => callee_index,
+                Some(_) => continue
+            };
+            let entry = self.indices.get_mut(&function).unwrap();
+            entry.1 = entry.1.min(low);
+        }
+
+        let (index, low) = self.indices[&function];
+        if index == low {
+            let position = self.stack.iter().position(|&f| f == function).unwrap();
+            let mut component = self.stack.split_off(position);
+            component.sort_by_key(|f| graph.functions.iter().position(|g| g == f));
+            self.components.push(component);
+        }
+    }
+}
+
+impl CallGraph {
+    pub fn new(program: &IRProgram) -> Self {
+        let functions: Vec<InternedName> = program.functions.iter().map(|(name, _)| *name).collect();
+        let calls = program.functions.iter().map(|(name, function)| {
+            let mut calls = Vec::new();
+            collect_calls(&function.body.body, &mut calls);
+            //Calls of undefined functions are reported during the conversion to IR
+            calls.retain(|call| functions.contains(&call.callee));
+            (*name, calls)
+        }).collect();
+        let mut main_calls = Vec::new();
+        collect_calls(&program.main.body, &mut main_calls);
+        CallGraph {
+            functions,
+            calls,
+            main_calls,
+        }
+    }
+
+    pub fn calls(&self, function: InternedName) -> &[Call] {
+        self.calls.get(&function).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    pub fn reachable_from_main(&self) -> HashSet<InternedName> {
+        let mut pending: Vec<InternedName> = self.main_calls.iter().map(|call| call.callee).collect();
+        let mut reachable = HashSet::new();
+        while let Some(function) = pending.pop() {
+            if reachable.insert(function) {
+                pending.extend(self.calls(function).iter().map(|call| call.callee));
+            }
+        }
+        reachable
+    }
+
+    //Callees come before their callers, so every component only calls itself and earlier ones
+    fn components(&self) -> Vec<Vec<InternedName>> {
+        let mut components = Components {
+            graph: self,
+            indices: HashMap::new(),
+            stack: Vec::new(),
+            components: Vec::new(),
+        };
+        for &function in &self.functions {
+            if !components.indices.contains_key(&function) {
+                components.visit(function);
+            }
+        }
+        components.components
+    }
+
+    //Groups of functions calling each other, a single function if it calls itself directly
+    pub fn recursive_groups(&self) -> Vec<Vec<InternedName>> {
+        let mut groups: Vec<Vec<InternedName>> = self.components().into_iter()
+            .filter(|component| component.len() > 1 || self.calls(component[0]).iter().any(|call| call.callee == component[0]))
+            .collect();
+        groups.sort_by_key(|group| self.functions.iter().position(|&f| f == group[0]));
+        groups
+    }
+
+    //Functions in an order where callees are defined before their callers. Recursive functions cannot be
+    //ordered like this, they are kept in source order.
+    pub fn topological_order(&self) -> Vec<InternedName> {
+        self.components().into_iter().flatten().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::callgraph::CallGraph;
+    use crate::ir::{convert_program_to_ir, DecrementPolicy};
+    use crate::codegen::OverflowPolicy;
+    use crate::parser::parse_program;
+    use crate::name::NameStore;
+    use crate::name::InternedName;
+
+    fn names(store: &NameStore, functions: &[InternedName]) -> Vec<String> {
+        functions.iter().map(|&f| store.get(f).unwrap().clone()).collect()
+    }
+
+    #[test]
+    fn test_call_graph() {
+        let code =
+            "input: x
+output: y
+def even(n):
+    while n!=0:
+        n-=1
+        r=odd(n)
+    return r
+def start(n):
+    r=twice(n)
+    r=even(r)
+    return r
+def odd(n):
+    r=even(n)
+    return r
+def twice(n):
+    r=add(n, n)
+    return r
+def add(a, b):
+    while a!=0:
+        a-=1
+        b+=1
+    return b
+def loop(n):
+    r=loop(n)
+    return r
+y=start(x)";
+        let (store, ast_res) = parse_program(code);
+        let ir = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        let graph = CallGraph::new(&ir);
+
+        let groups: Vec<Vec<String>> = graph.recursive_groups().iter().map(|group| names(&store, group)).collect();
+        assert_eq!(groups, vec![vec!["even", "odd"], vec!["loop"]]);
+        assert_eq!(names(&store, &graph.topological_order()), vec!["even", "odd", "add", "twice", "start", "loop"]);
+        assert_eq!(graph.reachable_from_main().len(), 5);
+    }
+}
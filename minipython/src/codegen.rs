@@ -1,95 +1,1010 @@
 use crate::ir::*;
 use crate::name::*;
 use std::error::Error;
-use std::io::{BufWriter, Write};
-use std::fs::File;
+use std::io::Write;
 use crate::value::Value;
+use crate::callgraph::CallGraph;
 use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
 
 const C_VALUE_TYPE: &str = "unsigned long long int";
 const C_VALUE_FORMAT: &str = "%llu";
+const C_BIGNUM_TYPE: &str = "mp_nat";
+
+//Keywords and functions of the included headers, e.g. div from the prelude clashes with div() from stdlib.h
+const C_RESERVED: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum", "extern", "float",
+    "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return", "short", "signed", "sizeof", "static",
+    "struct", "switch", "typedef", "union", "unsigned", "void", "volatile", "while", "bool", "true", "false", "main",
+    "abort", "abs", "atexit", "atof", "atoi", "atol", "calloc", "div", "exit", "exp", "free", "getenv", "labs", "ldiv",
+    "malloc", "printf", "putchar", "puts", "qsort", "rand", "realloc", "scanf", "srand", "strtol", "strtoul", "system",
+];
+
+//Library headers can be included from C++, so their parameter names must not be C++ keywords either
+const CPP_RESERVED: &[&str] = &[
+    "alignas", "alignof", "and", "and_eq", "asm", "bitand", "bitor", "catch", "char8_t", "char16_t", "char32_t", "class",
+    "compl", "concept", "consteval", "constexpr", "constinit", "const_cast", "co_await", "co_return", "co_yield",
+    "decltype", "delete", "dynamic_cast", "explicit", "export", "friend", "mutable", "namespace", "new", "noexcept", "not",
+    "not_eq", "nullptr", "operator", "or", "or_eq", "private", "protected", "public", "reinterpret_cast", "requires",
+    "static_assert", "static_cast", "template", "this", "thread_local", "throw", "try", "typeid", "typename", "using",
+    "virtual", "wchar_t", "xor", "xor_eq",
+];
+
+//Self-contained arbitrary-precision natural numbers, stored as little-endian base 2^32 digits
+const C_BIGNUM_RUNTIME: &str = "#include <stdlib.h>
+typedef struct {
+    unsigned int len;
+    unsigned int cap;
+    unsigned int* digits;
+} mp_nat;
+static mp_nat mp_zero(void) {
+    mp_nat n = { 0, 0, NULL };
+    return n;
+}
+static void mp_free(mp_nat* n) {
+    free(n->digits);
+    *n = mp_zero();
+}
+static void mp_reserve(mp_nat* n, unsigned int cap) {
+    if (cap > n->cap) {
+        unsigned int new_cap = n->cap ? n->cap * 2 : 4;
+        if (new_cap < cap) new_cap = cap;
+        n->digits = realloc(n->digits, new_cap * sizeof(unsigned int));
+        if (!n->digits) {
+            fprintf(stderr, \"Out of memory\\n\");
+            exit(1);
+        }
+        n->cap = new_cap;
+    }
+}
+static void mp_normalize(mp_nat* n) {
+    while (n->len && !n->digits[n->len - 1]) n->len--;
+}
+static int mp_is_zero(const mp_nat* n) {
+    return n->len == 0;
+}
+static mp_nat mp_copy(mp_nat n) {
+    mp_nat c = mp_zero();
+    unsigned int i;
+    mp_reserve(&c, n.len);
+    for (i = 0; i < n.len; i++) c.digits[i] = n.digits[i];
+    c.len = n.len;
+    return c;
+}
+static void mp_add_small(mp_nat* n, unsigned long long v) {
+    unsigned int i = 0;
+    while (v) {
+        unsigned long long sum;
+        if (i == n->len) {
+            mp_reserve(n, n->len + 1);
+            n->digits[n->len++] = 0;
+        }
+        sum = (unsigned long long)n->digits[i] + (v & 0xFFFFFFFFu);
+        n->digits[i] = (unsigned int)sum;
+        v = (v >> 32) + (sum >> 32);
+        i++;
+    }
+}
+/* There are no negative natural numbers, so subtraction saturates at zero */
+static void mp_sub_small(mp_nat* n, unsigned long long v) {
+    unsigned long long borrow = 0;
+    unsigned int i;
+    if (n->len <= 2) {
+        unsigned long long current = 0;
+        if (n->len > 0) current = n->digits[0];
+        if (n->len > 1) current |= (unsigned long long)n->digits[1] << 32;
+        if (current <= v) {
+            n->len = 0;
+            return;
+        }
+    }
+    for (i = 0; i < n->len && (v || borrow); i++) {
+        unsigned long long sub = (v & 0xFFFFFFFFu) + borrow;
+        v >>= 32;
+        if ((unsigned long long)n->digits[i] >= sub) {
+            n->digits[i] = (unsigned int)(n->digits[i] - sub);
+            borrow = 0;
+        } else {
+            n->digits[i] = (unsigned int)(0x100000000ull + n->digits[i] - sub);
+            borrow = 1;
+        }
+    }
+    mp_normalize(n);
+}
+static void mp_mul_small(mp_nat* n, unsigned int m) {
+    unsigned long long carry = 0;
+    unsigned int i;
+    for (i = 0; i < n->len; i++) {
+        unsigned long long p = (unsigned long long)n->digits[i] * m + carry;
+        n->digits[i] = (unsigned int)p;
+        carry = p >> 32;
+    }
+    if (carry) {
+        mp_reserve(n, n->len + 1);
+        n->digits[n->len++] = (unsigned int)carry;
+    }
+    mp_normalize(n);
+}
+static unsigned int mp_div_small(mp_nat* n, unsigned int d) {
+    unsigned long long rem = 0;
+    unsigned int i = n->len;
+    while (i > 0) {
+        unsigned long long current;
+        i--;
+        current = (rem << 32) | n->digits[i];
+        n->digits[i] = (unsigned int)(current / d);
+        rem = current % d;
+    }
+    mp_normalize(n);
+    return (unsigned int)rem;
+}
+static mp_nat mp_read(void) {
+    mp_nat n = mp_zero();
+    int c = getchar();
+    while (c == ' ' || c == '\\n' || c == '\\t' || c == '\\r') c = getchar();
+    while (c >= '0' && c <= '9') {
+        mp_mul_small(&n, 10);
+        mp_add_small(&n, (unsigned long long)(c - '0'));
+        c = getchar();
+    }
+    if (c != EOF) ungetc(c, stdin);
+    return n;
+}
+static void mp_print(mp_nat n) {
+    mp_nat tmp = mp_copy(n);
+    unsigned int* chunks;
+    unsigned int count = 0;
+    unsigned int i;
+    if (mp_is_zero(&tmp)) {
+        printf(\"0\");
+        return;
+    }
+    chunks = malloc((2 * tmp.len + 1) * sizeof(unsigned int));
+    if (!chunks) {
+        fprintf(stderr, \"Out of memory\\n\");
+        exit(1);
+    }
+    while (!mp_is_zero(&tmp)) chunks[count++] = mp_div_small(&tmp, 1000000000u);
+    printf(\"%u\", chunks[count - 1]);
+    for (i = count - 1; i > 0; i--) printf(\"%09u\", chunks[i - 1]);
+    free(chunks);
+    mp_free(&tmp);
+}
+";
+
+//Reads a 128-bit number, as scanf cannot handle them
+const C_U128_RUNTIME: &str = "static unsigned __int128 mp_read_u128(int* overflow) {
+    unsigned __int128 n = 0;
+    int c = getchar();
+    *overflow = 0;
+    while (c == ' ' || c == '\\n' || c == '\\t' || c == '\\r') c = getchar();
+    while (c >= '0' && c <= '9') {
+        unsigned __int128 d = (unsigned __int128)(c - '0');
+        if (n > (((unsigned __int128)-1) - d) / 10) *overflow = 1;
+        n = n * 10 + d;
+        c = getchar();
+    }
+    if (c != EOF) ungetc(c, stdin);
+    return n;
+}
+static void mp_print_u128(unsigned __int128 n) {
+    char buffer[40];
+    int i = 39;
+    buffer[i] = '\\0';
+    do {
+        buffer[--i] = (char)('0' + (int)(n % 10));
+        n /= 10;
+    } while (n);
+    printf(\"%s\", &buffer[i]);
+}
+";
+
+//Inputs that are not read from stdin are validated, as there is no prompt to retry
+const C_INPUT_RUNTIME: &str = "#include <stdlib.h>
+static void mp_invalid_input(const char* name, const char* text) {
+    if (text) fprintf(stderr, \"Invalid value for %s: %s\\n\", name, text);
+    else fprintf(stderr, \"Missing value for %s\\n\", name);
+    exit(2);
+}
+";
+
+//Parses a decimal number into mp_wide, setting overflow if it does not fit
+const C_PARSE_RUNTIME: &str = "static int mp_parse(const char* text, mp_wide* value, int* overflow) {
+    mp_wide n = 0;
+    *overflow = 0;
+    if (!text || !*text) return 0;
+    for (; *text; text++) {
+        mp_wide d;
+        if (*text < '0' || *text > '9') return 0;
+        d = (mp_wide)(*text - '0');
+        if (n > (((mp_wide)-1) - d) / 10) *overflow = 1;
+        n = n * 10 + d;
+    }
+    *value = n;
+    return 1;
+}
+";
+
+const C_PARSE_BIGNUM_RUNTIME: &str = "static int mp_parse(const char* text, mp_nat* value) {
+    mp_nat n = mp_zero();
+    if (!text || !*text) return 0;
+    for (; *text; text++) {
+        if (*text < '0' || *text > '9') return 0;
+        mp_mul_small(&n, 10);
+        mp_add_small(&n, (unsigned long long)(*text - '0'));
+    }
+    *value = n;
+    return 1;
+}
+";
+
+const C_TRAP_RUNTIME: &str = "#include <stdlib.h>
+static void mp_overflow(const char* name) {
+    fprintf(stderr, \"Overflow of %s\\n\", name);
+    exit(1);
+}
+";
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ValueRepresentation {
+    //Fixed-width unsigned integers
+    Native,
+    //Arbitrary-precision natural numbers, decrementing saturates at zero
+    BigNum,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum IntegerWidth {
+    W8,
+    W16,
+    W32,
+    W64,
+    W128,
+}
+
+impl IntegerWidth {
+    pub fn bits(&self) -> u32 {
+        match self {
+            IntegerWidth::W8 => 8,
+            IntegerWidth::W16 => 16,
+            IntegerWidth::W32 => 32,
+            IntegerWidth::W64 => 64,
+            IntegerWidth::W128 => 128
+        }
+    }
+
+    fn value_type(&self) -> &'static str {
+        match self {
+            IntegerWidth::W8 => "uint8_t",
+            IntegerWidth::W16 => "uint16_t",
+            IntegerWidth::W32 => "uint32_t",
+            IntegerWidth::W64 => C_VALUE_TYPE,
+            IntegerWidth::W128 => "unsigned __int128"
+        }
+    }
+
+    fn max(&self) -> u128 {
+        u128::MAX >> (128 - self.bits())
+    }
+
+    fn max_expr(&self) -> String {
+        match self {
+            IntegerWidth::W128 => String::from("((unsigned __int128)-1)"),
+            _ => format!("{}ull", self.max())
+        }
+    }
+
+    //Largest value that can be incremented by change without overflowing
+    fn threshold_expr(&self, change: u64) -> String {
+        match self {
+            IntegerWidth::W128 => format!("({} - {}ull)", self.max_expr(), change),
+            _ => format!("{}ull", self.max() - u128::from(change))
+        }
+    }
+}
+
+//What happens when an increment exceeds the maximum value
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum OverflowPolicy {
+    Wrap,
+    Saturate,
+    Trap,
+}
+
+//Where the generated program gets its inputs from
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum InputSource {
+    //Asks for every input on stdin
+    Prompt,
+    //Takes the inputs as command-line arguments, in the order they are declared
+    Arguments,
+    //Reads environment variables named like the inputs
+    Environment,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    //y=42
+    NameValue,
+    //42
+    Plain,
+    //{"y": 42}
+    Json,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct CodegenOptions {
+    pub representation: ValueRepresentation,
+    pub width: IntegerWidth,
+    pub overflow: OverflowPolicy,
+    pub decrement: DecrementPolicy,
+    pub input_source: InputSource,
+    pub output_format: OutputFormat,
+}
+
+impl Default for CodegenOptions {
+    fn default() -> Self {
+        CodegenOptions {
+            representation: ValueRepresentation::Native,
+            width: IntegerWidth::W64,
+            overflow: OverflowPolicy::Wrap,
+            decrement: DecrementPolicy::Wrap,
+            input_source: InputSource::Prompt,
+            output_format: OutputFormat::NameValue,
+        }
+    }
+}
+
+impl CodegenOptions {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.representation == ValueRepresentation::BigNum && (self.width != IntegerWidth::W64 || self.overflow != OverflowPolicy::Wrap) {
+            Err(String::from("Integer width and overflow policy cannot be combined with arbitrary-precision values"))
+        } else {
+            Ok(())
+        }
+    }
+
+    //Bignums cannot wrap around, so decrementing zero always saturates
+    pub fn decrement_policy(&self) -> DecrementPolicy {
+        match self.representation {
+            ValueRepresentation::Native => self.decrement,
+            ValueRepresentation::BigNum => DecrementPolicy::Saturate
+        }
+    }
+
+    fn value_type(&self) -> &'static str {
+        match self.representation {
+            ValueRepresentation::Native => self.width.value_type(),
+            ValueRepresentation::BigNum => C_BIGNUM_TYPE
+        }
+    }
+}
 
 fn to_value_name(v: Value, name_store: &NameStore) -> String {
     format!("{}_{}", v.get_name(name_store).unwrap(), v.get_id())
 }
 
-fn write_value_init(output: &mut BufWriter<&File>, output_name: &String) -> Result<(), Box<dyn Error>> {
-    writeln!(output, "{} {} = 0;", C_VALUE_TYPE, output_name)?;
+//Parameter names in library headers, which have no id suffix
+fn to_header_param_name(v: Value, name_store: &NameStore) -> String {
+    let name = v.get_name(name_store).unwrap();
+    if C_RESERVED.contains(&name.as_str()) || CPP_RESERVED.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else {
+        name.clone()
+    }
+}
+
+//The prefix of C libraries already keeps function names apart from the reserved ones
+fn to_function_name(name: InternedName, name_store: &NameStore, prefix: &str) -> String {
+    let name = name_store.get(name).unwrap();
+    if prefix.is_empty() && C_RESERVED.contains(&name.as_str()) {
+        format!("{}_", name)
+    } else {
+        format!("{}{}", prefix, name)
+    }
+}
+
+fn write_value_init(output: &mut dyn Write, output_name: &String, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    match options.representation {
+        ValueRepresentation::Native => writeln!(output, "{} {} = 0;", options.value_type(), output_name)?,
+        ValueRepresentation::BigNum => writeln!(output, "{} {} = mp_zero();", C_BIGNUM_TYPE, output_name)?
+    }
+    Ok(())
+}
+
+fn write_increment(output: &mut dyn Write, val: Value, change: u64, name_store: &NameStore, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    let val_name = to_value_name(val, name_store);
+    let width = options.width;
+    let always_overflows = u128::from(change) > width.max();
+    match options.overflow {
+        OverflowPolicy::Wrap => writeln!(output, "{} += {};", val_name, change)?,
+        OverflowPolicy::Saturate => if always_overflows {
+            writeln!(output, "{} = {};", val_name, width.max_expr())?
+        } else {
+            //An unsigned literal, narrow values would be promoted to int otherwise
+            writeln!(output, "{} = {} > {} ? {} : {} + {}ull;", val_name, val_name, width.threshold_expr(change), width.max_expr(), val_name, change)?
+        },
+        OverflowPolicy::Trap => {
+            let name = val.get_name(name_store).unwrap();
+            if always_overflows {
+                writeln!(output, "mp_overflow(\"{}\");", name)?
+            } else {
+                writeln!(output, "if ({} > {}) mp_overflow(\"{}\");", val_name, width.threshold_expr(change), name)?;
+                writeln!(output, "{} += {};", val_name, change)?
+            }
+        }
+    }
     Ok(())
 }
 
-fn compile_statement(statement: &IRStatement, name_store: &NameStore, output: &mut BufWriter<&File>) -> Result<(), Box<dyn Error>> {
+//Bignums own heap memory, so every value of a function has to be freed before returning
+//Functions are called with the prefix of their symbol
+fn compile_statement(statement: &IRStatement, name_store: &NameStore, options: &CodegenOptions, prefix: &str, owned: &[Value], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
     match statement {
-        ValueModify(val, change) => {
+        ValueModify(val, change, _) => {
             let val_name = to_value_name(*val, name_store);
-            writeln!(output, "{} += {};", val_name, change)?;
+            match options.representation {
+                ValueRepresentation::Native => if *change >= 0 {
+                    write_increment(output, *val, *change as u64, name_store, options)?
+                } else if options.decrement == DecrementPolicy::Saturate {
+                    let decrement = change.unsigned_abs();
+                    writeln!(output, "{} = {} < {}ull ? 0 : {} - {}ull;", val_name, val_name, decrement, val_name, decrement)?
+                } else {
+                    writeln!(output, "{} += {};", val_name, change)?
+                },
+                ValueRepresentation::BigNum => if *change >= 0 {
+                    writeln!(output, "mp_add_small(&{}, {}ull);", val_name, change)?
+                } else {
+                    writeln!(output, "mp_sub_small(&{}, {}ull);", val_name, change.unsigned_abs())?
+                }
+            }
         },
-        Return(val) => {
+        Return(val, _) => {
             let val_name = to_value_name(*val, name_store);
-            writeln!(output, "return {};", val_name)?;
+            match options.representation {
+                ValueRepresentation::Native => writeln!(output, "return {};", val_name)?,
+                ValueRepresentation::BigNum => {
+                    writeln!(output, "{{")?;
+                    writeln!(output, "{} result = {};", C_BIGNUM_TYPE, val_name)?;
+                    writeln!(output, "{} = mp_zero();", val_name)?;
+                    for &owned_val in owned {
+                        writeln!(output, "mp_free(&{});", to_value_name(owned_val, name_store))?;
+                    }
+                    writeln!(output, "return result;")?;
+                    writeln!(output, "}}")?;
+                }
+            }
         },
-        Loop { condition_var, body } => {
-            writeln!(output, "while ({}) {{", to_value_name(*condition_var, name_store))?;
+        Loop { condition_var, body, .. } => {
+            let cond_name = to_value_name(*condition_var, name_store);
+            match options.representation {
+                ValueRepresentation::Native => writeln!(output, "while ({}) {{", cond_name)?,
+                ValueRepresentation::BigNum => writeln!(output, "while (!mp_is_zero(&{})) {{", cond_name)?
+            }
 
             for st in body {
-                compile_statement(st, name_store, output)?;
+                compile_statement(st, name_store, options, prefix, owned, output)?;
             }
 
             writeln!(output, "}}")?;
         },
-        FunctionCall { func, args, target } => {
-            let func_name = name_store.get(*func).unwrap();
+        FunctionCall { func, args, target, .. } => {
+            let func_name = to_function_name(*func, name_store, prefix);
             let target_name = to_value_name(*target, name_store);
-            let args_names = args.iter().map(|&v| to_value_name(v, name_store)).collect::<Vec<String>>().join(", ");
-            writeln!(output, "{} = {}({});", target_name, func_name, args_names)?;
+            match options.representation {
+                ValueRepresentation::Native => {
+                    let args_names = args.iter().map(|&v| to_value_name(v, name_store)).collect::<Vec<String>>().join(", ");
+                    writeln!(output, "{} = {}({});", target_name, func_name, args_names)?;
+                },
+                ValueRepresentation::BigNum => {
+                    //Functions take ownership of their parameters, so the arguments are copied
+                    let args_copies = args.iter().map(|&v| format!("mp_copy({})", to_value_name(v, name_store))).collect::<Vec<String>>().join(", ");
+                    writeln!(output, "{{")?;
+                    writeln!(output, "{} result = {}({});", C_BIGNUM_TYPE, func_name, args_copies)?;
+                    writeln!(output, "mp_free(&{});", target_name)?;
+                    writeln!(output, "{} = result;", target_name)?;
+                    writeln!(output, "}}")?;
+                }
+            }
         }
     }
     Ok(())
 }
 
-fn compile_block(block: &IRBlock, name_store: &NameStore, output: &mut BufWriter<&File>) -> Result<(), Box<dyn Error>> {
+fn compile_block(block: &IRBlock, name_store: &NameStore, options: &CodegenOptions, prefix: &str, owned: &[Value], output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
     for &val in &block.values {
         let val_name = to_value_name(val, name_store);
-        write_value_init(output, &val_name)?;
+        write_value_init(output, &val_name, options)?;
     }
 
     for statement in &block.body {
-        compile_statement(statement, name_store, output)?;
+        compile_statement(statement, name_store, options, prefix, owned, output)?;
+    }
+
+    Ok(())
+}
+
+//Inputs that do not fit into the value type are handled like overflowing increments
+fn write_input_overflow(output: &mut dyn Write, val_name: &str, name: &str, read_overflow: &str, read_value: &str, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    let width = options.width;
+    match options.overflow {
+        OverflowPolicy::Wrap => writeln!(output, "{} = {};", val_name, read_value)?,
+        OverflowPolicy::Saturate => writeln!(output, "{} = {} ? {} : {};", val_name, read_overflow, width.max_expr(), read_value)?,
+        OverflowPolicy::Trap => {
+            writeln!(output, "if ({}) mp_overflow(\"{}\");", read_overflow, name)?;
+            writeln!(output, "{} = {};", val_name, read_value)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_prompted_input(output: &mut dyn Write, val_name: &str, name: &str, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    writeln!(output, "printf(\"{}=\");", name)?;
+
+    if options.representation == ValueRepresentation::BigNum {
+        writeln!(output, "{} = mp_read();", val_name)?;
+        return Ok(());
     }
 
+    let width = options.width;
+    let (read_overflow, read_value) = match width {
+        IntegerWidth::W64 => {
+            writeln!(output, "scanf(\"{}\", &{});", C_VALUE_FORMAT, val_name)?;
+            return Ok(());
+        },
+        IntegerWidth::W128 => {
+            writeln!(output, "{{")?;
+            writeln!(output, "int overflow;")?;
+            writeln!(output, "{} input = mp_read_u128(&overflow);", width.value_type())?;
+            (String::from("overflow"), String::from("input"))
+        },
+        _ => {
+            writeln!(output, "{{")?;
+            writeln!(output, "{} input;", C_VALUE_TYPE)?;
+            writeln!(output, "scanf(\"{}\", &input);", C_VALUE_FORMAT)?;
+            (format!("input > {}", width.max_expr()), format!("({})input", width.value_type()))
+        }
+    };
+    write_input_overflow(output, val_name, name, &read_overflow, &read_value, options)?;
+    writeln!(output, "}}")?;
     Ok(())
 }
 
-pub fn compile_to_c(program: &IRProgram, name_store: &NameStore, output: &mut BufWriter<&File>) -> Result<(), Box<dyn Error>> {
-    //Include stdio
-    writeln!(output, "#include <stdio.h>")?;
+//Index is the position of the input in the declaration
+fn write_input(output: &mut dyn Write, input_val: Value, index: usize, name_store: &NameStore, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    let val_name = to_value_name(input_val, name_store);
+    let name = input_val.get_name(name_store).unwrap();
+    writeln!(output, "{} {};", options.value_type(), val_name)?;
 
-    for (&function_name, function) in &program.functions {
-        let params = function.params.iter().map(|&v| format!("{} {}", C_VALUE_TYPE, to_value_name(v, name_store))).collect::<Vec<String>>().join(", ");
-        writeln!(output, "{} {}({}) {{", C_VALUE_TYPE, name_store.get(function_name).unwrap(), params)?;
+    let text = match options.input_source {
+        InputSource::Prompt => return write_prompted_input(output, &val_name, name, options),
+        InputSource::Arguments => format!("argv[{}]", index + 1),
+        InputSource::Environment => format!("getenv(\"{}\")", name)
+    };
+    writeln!(output, "{{")?;
+    writeln!(output, "const char* text = {};", text)?;
+    match options.representation {
+        ValueRepresentation::BigNum => writeln!(output, "if (!mp_parse(text, &{})) mp_invalid_input(\"{}\", text);", val_name, name)?,
+        ValueRepresentation::Native => {
+            writeln!(output, "mp_wide input;")?;
+            writeln!(output, "int overflow;")?;
+            writeln!(output, "if (!mp_parse(text, &input, &overflow)) mp_invalid_input(\"{}\", text);", name)?;
+            let read_overflow = match options.width {
+                IntegerWidth::W64 | IntegerWidth::W128 => String::from("overflow"),
+                width => format!("overflow || input > {}", width.max_expr())
+            };
+            let read_value = format!("({})input", options.width.value_type());
+            write_input_overflow(output, &val_name, name, &read_overflow, &read_value, options)?;
+        }
+    }
+    writeln!(output, "}}")?;
+    Ok(())
+}
 
-        compile_block(&function.body, name_store, output)?;
+fn write_output(output: &mut dyn Write, output_val: Value, name_store: &NameStore, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    let output_name = to_value_name(output_val, name_store);
+    let name = output_val.get_name(name_store).unwrap();
+    let (prefix, suffix) = match options.output_format {
+        OutputFormat::NameValue => (format!("{}=", name), String::new()),
+        OutputFormat::Plain => (String::new(), String::new()),
+        OutputFormat::Json => (format!("{{\\\"{}\\\": ", name), String::from("}"))
+    };
+    //Prompted programs keep the output on the line of the last prompt, like they always did
+    let suffix = if options.input_source == InputSource::Prompt { suffix } else { format!("{}\\n", suffix) };
+    match (options.representation, options.width) {
+        (ValueRepresentation::Native, IntegerWidth::W64) => writeln!(output, "printf(\"{}{}{}\", {});", prefix, C_VALUE_FORMAT, suffix, output_name)?,
+        (ValueRepresentation::Native, IntegerWidth::W8) | (ValueRepresentation::Native, IntegerWidth::W16) | (ValueRepresentation::Native, IntegerWidth::W32) => {
+            writeln!(output, "printf(\"{}{}{}\", ({}){});", prefix, C_VALUE_FORMAT, suffix, C_VALUE_TYPE, output_name)?
+        },
+        (representation, _) => {
+            if !prefix.is_empty() {
+                writeln!(output, "printf(\"{}\");", prefix)?;
+            }
+            match representation {
+                ValueRepresentation::BigNum => writeln!(output, "mp_print({});", output_name)?,
+                ValueRepresentation::Native => writeln!(output, "mp_print_u128({});", output_name)?
+            }
+            if !suffix.is_empty() {
+                writeln!(output, "printf(\"{}\");", suffix)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+//Libraries do no I/O, so they only get the helpers for the values and the overflow error message
+fn write_runtime(options: &CodegenOptions, with_io: bool, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if with_io || options.overflow == OverflowPolicy::Trap {
+        writeln!(output, "#include <stdio.h>")?;
+    }
+
+    match options.representation {
+        ValueRepresentation::BigNum => write!(output, "{}", C_BIGNUM_RUNTIME)?,
+        ValueRepresentation::Native => {
+            match options.width {
+                IntegerWidth::W8 | IntegerWidth::W16 | IntegerWidth::W32 => writeln!(output, "#include <stdint.h>")?,
+                IntegerWidth::W128 if with_io => write!(output, "{}", C_U128_RUNTIME)?,
+                IntegerWidth::W64 | IntegerWidth::W128 => ()
+            }
+            if options.overflow == OverflowPolicy::Trap {
+                write!(output, "{}", C_TRAP_RUNTIME)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn write_prototype(output: &mut dyn Write, name: &str, params: &[String], options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    let params = params.iter().map(|p| format!("{} {}", options.value_type(), p)).collect::<Vec<String>>().join(", ");
+    writeln!(output, "{} {}({});", options.value_type(), name, params)?;
+    Ok(())
+}
+
+fn write_functions(program: &IRProgram, name_store: &NameStore, options: &CodegenOptions, prefix: &str, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let value_type = options.value_type();
+
+    //Callees are defined before their callers, the declarations are still needed for recursive functions
+    let order = CallGraph::new(program).topological_order();
+    for &function_name in &order {
+        let params = vec![value_type; program.function(function_name).unwrap().params.len()].join(", ");
+        writeln!(output, "{} {}({});", value_type, to_function_name(function_name, name_store, prefix), params)?;
+    }
+
+    for &function_name in &order {
+        let function = program.function(function_name).unwrap();
+        let params = function.params.iter().map(|&v| format!("{} {}", value_type, to_value_name(v, name_store))).collect::<Vec<String>>().join(", ");
+        writeln!(output, "{} {}({}) {{", value_type, to_function_name(function_name, name_store, prefix), params)?;
+
+        let mut owned = function.params.clone();
+        owned.extend(&function.body.values);
+        compile_block(&function.body, name_store, options, prefix, &owned, output)?;
 
         writeln!(output, "}}")?;
     }
+    Ok(())
+}
+
+pub fn compile_to_c(program: &IRProgram, name_store: &NameStore, options: &CodegenOptions, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    options.validate()?;
+    write_runtime(options, true, output)?;
+
+    if options.input_source != InputSource::Prompt {
+        write!(output, "{}", C_INPUT_RUNTIME)?;
+        match options.representation {
+            ValueRepresentation::BigNum => write!(output, "{}", C_PARSE_BIGNUM_RUNTIME)?,
+            ValueRepresentation::Native => {
+                //Wide enough to detect inputs that do not fit into the value type
+                let wide_type = if options.width == IntegerWidth::W128 { IntegerWidth::W128.value_type() } else { C_VALUE_TYPE };
+                writeln!(output, "typedef {} mp_wide;", wide_type)?;
+                write!(output, "{}", C_PARSE_RUNTIME)?;
+            }
+        }
+    }
+
+    write_functions(program, name_store, options, "", output)?;
 
     writeln!(output, "int main(int argc, char* argv[]) {{")?;
 
-    for &input_val in &program.inputs {
-        let val_name = to_value_name(input_val, name_store);
-        writeln!(output, "{} {};", C_VALUE_TYPE, val_name)?;
-        writeln!(output, "printf(\"{}=\");", input_val.get_name(name_store).unwrap())?;
-        writeln!(output, "scanf(\"{}\", &{});", C_VALUE_FORMAT, val_name)?;
+    if options.input_source == InputSource::Arguments {
+        let names = program.inputs.iter().map(|v| format!(" {}", v.get_name(name_store).unwrap())).collect::<String>();
+        writeln!(output, "if (argc != {}) {{", program.inputs.len() + 1)?;
+        writeln!(output, "fprintf(stderr, \"Usage: %s{}\\n\", argv[0]);", names)?;
+        writeln!(output, "return 2;")?;
+        writeln!(output, "}}")?;
+    }
+
+    for (index, &input_val) in program.inputs.iter().enumerate() {
+        write_input(output, input_val, index, name_store, options)?;
     }
 
     let output_name = to_value_name(program.output, name_store);
-    write_value_init(output, &output_name)?;
+    write_value_init(output, &output_name, options)?;
 
-    compile_block(&program.main, name_store, output)?;
+    compile_block(&program.main, name_store, options, "", &[], output)?;
 
-    writeln!(output, "printf(\"{}={}\", {});", program.output.get_name(name_store).unwrap(), C_VALUE_FORMAT, output_name)?;
+    write_output(output, program.output, name_store, options)?;
     writeln!(output, "return 0;")?;
     writeln!(output, "}}")?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+//Only the functions and the main block are exported, the runtime helpers stay static
+pub fn compile_to_c_lib(program: &IRProgram, name_store: &NameStore, options: &CodegenOptions, prefix: &str, header_name: &str, header: &mut dyn Write, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    options.validate()?;
+    let entry_point = format!("{}main", prefix);
+    if program.functions.iter().any(|(name, _)| format!("{}{}", prefix, name_store.get(*name).unwrap()) == entry_point) {
+        return Err(Box::from(format!("Function main conflicts with the entry point {} of the library", entry_point)));
+    }
+
+    let guard: String = header_name.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' }).collect();
+    writeln!(header, "#ifndef {}", guard)?;
+    writeln!(header, "#define {}", guard)?;
+    if let IntegerWidth::W8 | IntegerWidth::W16 | IntegerWidth::W32 = options.width {
+        writeln!(header, "#include <stdint.h>")?;
+    }
+    writeln!(header, "#ifdef __cplusplus")?;
+    writeln!(header, "extern \"C\" {{")?;
+    writeln!(header, "#endif")?;
+    for (name, function) in &program.functions {
+        let params: Vec<String> = function.params.iter().map(|&v| to_header_param_name(v, name_store)).collect();
+        write_prototype(header, &format!("{}{}", prefix, name_store.get(*name).unwrap()), &params, options)?;
+    }
+    writeln!(header, "/* Runs the main block of the program, returning the output */")?;
+    let inputs: Vec<String> = program.inputs.iter().map(|&v| to_header_param_name(v, name_store)).collect();
+    write_prototype(header, &entry_point, &inputs, options)?;
+    writeln!(header, "#ifdef __cplusplus")?;
+    writeln!(header, "}}")?;
+    writeln!(header, "#endif")?;
+    writeln!(header, "#endif")?;
+
+    writeln!(output, "#include \"{}\"", header_name)?;
+    write_runtime(options, false, output)?;
+    write_functions(program, name_store, options, prefix, output)?;
+
+    let inputs: Vec<String> = program.inputs.iter().map(|&v| to_value_name(v, name_store)).collect();
+    let params = inputs.iter().map(|p| format!("{} {}", options.value_type(), p)).collect::<Vec<String>>().join(", ");
+    writeln!(output, "{} {}({}) {{", options.value_type(), entry_point, params)?;
+    let output_name = to_value_name(program.output, name_store);
+    write_value_init(output, &output_name, options)?;
+    compile_block(&program.main, name_store, options, prefix, &[], output)?;
+    writeln!(output, "return {};", output_name)?;
+    writeln!(output, "}}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::*;
+    use crate::compiler::{compile_to_string, emit_c_lib, lower, optimize, parse, resolve, CheckOptions, Target};
+    use std::process::{Command, Stdio};
+    use std::io::Write;
+    use std::fs;
+
+    struct RunResult {
+        stdout: String,
+        stderr: String,
+        success: bool,
+    }
+
+    //Compiles the program with gcc and runs it. Panics if gcc is not installed, the tests must not pass without running.
+    fn run_c(name: &str, code: &str, options: CodegenOptions, stdin: &str, args: &[&str], env: &[(&str, &str)]) -> RunResult {
+        let (c_code, _) = compile_to_string(code, Target::C, &options, &CheckOptions::default(), &[]).unwrap();
+        let dir = std::env::temp_dir().join(format!("minipython-codegen-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("main.c"), c_code).unwrap();
+        let mut gcc = Command::new("gcc");
+        gcc.arg("-o").arg(dir.join("main")).arg(dir.join("main.c"));
+        let compiled = gcc.output().expect("gcc is required to run the C code generation tests");
+        assert!(compiled.status.success(), "gcc failed: {}", String::from_utf8_lossy(&compiled.stderr));
+
+        let mut child = Command::new(dir.join("main")).args(args).envs(env.iter().cloned())
+            .stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped())
+            .spawn().unwrap();
+        child.stdin.take().unwrap().write_all(stdin.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        RunResult {
+            stdout: String::from_utf8(output.stdout).unwrap(),
+            stderr: String::from_utf8(output.stderr).unwrap(),
+            success: output.status.success(),
+        }
+    }
+
+    //Compiles the program as a library with the prefix lib_ and links it with a C file that calls it.
+    //Warnings are errors, as libraries end up in other projects, which may build with them enabled.
+    fn run_c_lib(name: &str, code: &str, options: CodegenOptions, driver: &str) -> RunResult {
+        let (mut name_store, mut program) = parse(code).unwrap();
+        resolve(&mut program, &mut name_store, None, &[], true).unwrap();
+        let mut ir = lower(&program, &name_store, &options).unwrap();
+        optimize(&mut ir);
+        let mut header = Vec::new();
+        let mut source = Vec::new();
+        emit_c_lib(&ir, &name_store, &options, "lib_", "lib.h", &mut header, &mut source).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("minipython-codegen-lib-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("lib.h"), header).unwrap();
+        fs::write(dir.join("lib.c"), source).unwrap();
+        fs::write(dir.join("main.c"), format!("#include <stdio.h>\n#include \"lib.h\"\n{}", driver)).unwrap();
+        let mut gcc = Command::new("gcc");
+        gcc.args(["-Wall", "-Wextra", "-Werror", "-o"]).arg(dir.join("main")).arg(dir.join("lib.c")).arg(dir.join("main.c"));
+        let compiled = gcc.output().expect("gcc is required to run the C code generation tests");
+        assert!(compiled.status.success(), "gcc failed: {}", String::from_utf8_lossy(&compiled.stderr));
+        //The header has to work in C++ as well
+        let mut gpp = Command::new("g++");
+        gpp.args(["-x", "c++", "-Wall", "-Wextra", "-Werror", "-c", "-o"]).arg(dir.join("main-cpp.o")).arg(dir.join("main.c"));
+        let compiled = gpp.output().expect("g++ is required to run the C library tests");
+        assert!(compiled.status.success(), "g++ failed: {}", String::from_utf8_lossy(&compiled.stderr));
+
+        let output = Command::new(dir.join("main")).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        RunResult {
+            stdout: String::from_utf8(output.stdout).unwrap(),
+            stderr: String::from_utf8(output.stderr).unwrap(),
+            success: output.status.success(),
+        }
+    }
+
+    //The value after the last =, the prompts are on the same line as the output
+    fn output_value(result: &RunResult) -> &str {
+        result.stdout.rsplit(['=', '\n']).find(|s| !s.is_empty()).unwrap()
+    }
+
+    fn native(width: IntegerWidth, overflow: OverflowPolicy) -> CodegenOptions {
+        CodegenOptions { width, overflow, ..CodegenOptions::default() }
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        //The increment overflows before the decrement, so the two cannot be merged unless values wrap around
+        let code = "input: x\noutput: y\ndef f(a):\n    a+=1\n    a-=1\n    return a\ny=f(x)\n";
+        let wrapped = run_c("wrap", code, native(IntegerWidth::W8, OverflowPolicy::Wrap), "255\n", &[], &[]);
+        assert_eq!(output_value(&wrapped), "255");
+        let saturated = run_c("saturate", code, native(IntegerWidth::W8, OverflowPolicy::Saturate), "255\n", &[], &[]);
+        assert_eq!(output_value(&saturated), "254");
+        let trapped = run_c("trap", code, native(IntegerWidth::W8, OverflowPolicy::Trap), "255\n", &[], &[]);
+        assert!(!trapped.success);
+        assert!(trapped.stderr.contains("Overflow of a"));
+        let fits = run_c("trap-fits", code, native(IntegerWidth::W8, OverflowPolicy::Trap), "254\n", &[], &[]);
+        assert_eq!(output_value(&fits), "254");
+    }
+
+    const INCREMENT: &str = "input: x\noutput: y\ndef inc(a):\n    a+=1\n    return a\ny=inc(x)\n";
+    const DECREMENT: &str = "input: x\noutput: y\ndef dec(a):\n    a-=1\n    return a\ny=dec(x)\n";
+
+    #[test]
+    fn test_bignum() {
+        let options = CodegenOptions { representation: ValueRepresentation::BigNum, ..CodegenOptions::default() };
+        //The digits are 32 bits wide, so the carries at 2^32, 2^64 and 2^128 need new digits
+        let carries = [
+            ("4294967295", "4294967296"),
+            ("18446744073709551615", "18446744073709551616"),
+            ("340282366920938463463374607431768211455", "340282366920938463463374607431768211456"),
+        ];
+        for (input, expected) in &carries {
+            let result = run_c("bignum-inc", INCREMENT, options, &format!("{}\n", input), &[], &[]);
+            assert_eq!(output_value(&result), *expected);
+            let result = run_c("bignum-dec", DECREMENT, options, &format!("{}\n", expected), &[], &[]);
+            assert_eq!(output_value(&result), *input);
+        }
+        //Decrementing zero saturates
+        let result = run_c("bignum-zero", DECREMENT, options, "0\n", &[], &[]);
+        assert_eq!(output_value(&result), "0");
+
+        //Arguments are copied when calling functions, so changing a parameter does not change the argument
+        let code = "input: x\noutput: y\ndef inc(a):\n    a+=1\n    return a\ny=inc(x)\ny=inc(x)\n";
+        let result = run_c("bignum-copy", code, options, "18446744073709551616\n", &[], &[]);
+        assert_eq!(output_value(&result), "18446744073709551617");
+    }
+
+    #[test]
+    fn test_widths() {
+        let widths = [
+            (IntegerWidth::W8, "255", Some("256")),
+            (IntegerWidth::W16, "65535", Some("65536")),
+            (IntegerWidth::W32, "4294967295", Some("4294967296")),
+            //scanf cannot detect 64 bit inputs that are too large
+            (IntegerWidth::W64, "18446744073709551615", None),
+            (IntegerWidth::W128, "340282366920938463463374607431768211455", Some("340282366920938463463374607431768211456")),
+        ];
+        for &(width, max, too_large) in &widths {
+            let run = |overflow, input: &str| run_c(&format!("width-{}", width.bits()), INCREMENT, native(width, overflow), &format!("{}\n", input), &[], &[]);
+            let wrapped = run(OverflowPolicy::Wrap, max);
+            assert_eq!(output_value(&wrapped), "0", "{:?}", width);
+            assert_eq!(output_value(&run(OverflowPolicy::Saturate, max)), max, "{:?}", width);
+            assert!(!run(OverflowPolicy::Trap, max).success, "{:?}", width);
+
+            //Inputs that do not fit are handled like an overflowing increment
+            if let Some(too_large) = too_large {
+                assert_eq!(output_value(&run(OverflowPolicy::Wrap, too_large)), "1", "{:?}", width);
+                assert_eq!(output_value(&run(OverflowPolicy::Saturate, too_large)), max, "{:?}", width);
+                assert!(!run(OverflowPolicy::Trap, too_large).success, "{:?}", width);
+            }
+        }
+
+        //128 bit values carry from the lower to the upper 64 bits
+        let result = run_c("width-carry", INCREMENT, native(IntegerWidth::W128, OverflowPolicy::Trap), "18446744073709551615\n", &[], &[]);
+        assert_eq!(output_value(&result), "18446744073709551616");
+        let result = run_c("width-borrow", DECREMENT, native(IntegerWidth::W128, OverflowPolicy::Wrap), "18446744073709551616\n", &[], &[]);
+        assert_eq!(output_value(&result), "18446744073709551615");
+    }
+
+    #[test]
+    fn test_saturating_decrement() {
+        //The two decrements are merged into one
+        let code = "input: x\noutput: y\ndef dec(a):\n    a-=1\n    a-=1\n    return a\ny=dec(x)\n";
+        let saturating = CodegenOptions { decrement: DecrementPolicy::Saturate, ..CodegenOptions::default() };
+        let result = run_c("saturating-decrement", code, saturating, "1\n", &[], &[]);
+        assert_eq!(output_value(&result), "0");
+        let result = run_c("saturating-decrement-5", code, saturating, "5\n", &[], &[]);
+        assert_eq!(output_value(&result), "3");
+        let result = run_c("wrapping-decrement", code, CodegenOptions::default(), "1\n", &[], &[]);
+        assert_eq!(output_value(&result), "18446744073709551615");
+
+        let saturating_8 = CodegenOptions { width: IntegerWidth::W8, ..saturating };
+        let result = run_c("saturating-decrement-8", DECREMENT, saturating_8, "0\n", &[], &[]);
+        assert_eq!(output_value(&result), "0");
+    }
+
+    #[test]
+    fn test_inputs_and_outputs() {
+        let code = "input: a, b\noutput: y\ny=add(a, b)\n";
+        let arguments = CodegenOptions { input_source: InputSource::Arguments, ..CodegenOptions::default() };
+        let result = run_c("arguments", code, arguments, "", &["2", "3"], &[]);
+        assert_eq!(result.stdout, "y=5\n");
+        let missing = run_c("arguments-missing", code, arguments, "", &["2"], &[]);
+        assert!(!missing.success);
+        assert!(missing.stderr.starts_with("Usage: "));
+        assert!(missing.stderr.ends_with(" a b\n"));
+        let invalid = run_c("arguments-invalid", code, arguments, "", &["2", "x3"], &[]);
+        assert!(!invalid.success);
+        assert_eq!(invalid.stderr, "Invalid value for b: x3\n");
+
+        let environment = CodegenOptions { input_source: InputSource::Environment, output_format: OutputFormat::Json, ..CodegenOptions::default() };
+        let result = run_c("environment", code, environment, "", &[], &[("a", "2"), ("b", "3")]);
+        assert_eq!(result.stdout, "{\"y\": 5}\n");
+        let missing = run_c("environment-missing", code, environment, "", &[], &[("a", "2")]);
+        assert_eq!(missing.stderr, "Missing value for b\n");
+
+        let plain = CodegenOptions { output_format: OutputFormat::Plain, ..CodegenOptions::default() };
+        let result = run_c("plain", code, plain, "2\n3\n", &[], &[]);
+        assert_eq!(result.stdout, "a=b=5");
+
+        //Values that need the runtimes for large numbers
+        let bignum = CodegenOptions { representation: ValueRepresentation::BigNum, ..arguments };
+        let result = run_c("arguments-bignum", INCREMENT, bignum, "", &["18446744073709551616"], &[]);
+        assert_eq!(result.stdout, "y=18446744073709551617\n");
+        let wide = CodegenOptions { width: IntegerWidth::W128, overflow: OverflowPolicy::Trap, ..environment };
+        let result = run_c("environment-128", INCREMENT, wide, "", &[], &[("x", "18446744073709551616")]);
+        assert_eq!(result.stdout, "{\"y\": 18446744073709551617}\n");
+        let result = run_c("environment-overflow", INCREMENT, wide, "", &[], &[("x", "340282366920938463463374607431768211456")]);
+        assert_eq!(result.stderr, "Overflow of x\n");
+        let narrow = CodegenOptions { width: IntegerWidth::W8, overflow: OverflowPolicy::Saturate, ..arguments };
+        let result = run_c("arguments-8", INCREMENT, narrow, "", &["1000"], &[]);
+        assert_eq!(result.stdout, "y=255\n");
+    }
+
+    #[test]
+    fn test_c_lib() {
+        //The parameters are C and C++ keywords, which are renamed in the header like the functions of C programs
+        let code = "input: int\noutput: default\ndef inc(auto, class):\n    auto+=1\n    while class!=0:\n        auto+=1\n        class-=1\n    #endwhile\n    return auto\n#enddef\ndefault=inc(int, default)\ndefault=inc(default, default)\n";
+        let driver = "int main(void) {\n    printf(\"%llu %llu\", (unsigned long long)lib_inc(40, 1), (unsigned long long)lib_main(1));\n    return 0;\n}\n";
+        let result = run_c_lib("wrap", code, CodegenOptions::default(), driver);
+        assert_eq!(result.stdout, "42 5");
+        let result = run_c_lib("8", code, native(IntegerWidth::W8, OverflowPolicy::Saturate), driver);
+        assert_eq!(result.stdout, "42 5");
+        let result = run_c_lib("128", code, native(IntegerWidth::W128, OverflowPolicy::Wrap), driver);
+        assert_eq!(result.stdout, "42 5");
+
+        //The overflow message is the only output of a library
+        let overflow = "int main(void) {\n    printf(\"%llu\", (unsigned long long)lib_main(254));\n    return 0;\n}\n";
+        let result = run_c_lib("saturate", code, native(IntegerWidth::W8, OverflowPolicy::Saturate), overflow);
+        assert_eq!(result.stdout, "255");
+        let result = run_c_lib("trap", code, native(IntegerWidth::W8, OverflowPolicy::Trap), overflow);
+        assert!(!result.success);
+        assert_eq!(result.stderr, "Overflow of auto\n");
+        let result = run_c_lib("trap-128", code, native(IntegerWidth::W128, OverflowPolicy::Trap), driver);
+        assert_eq!(result.stdout, "42 5");
+    }
+}
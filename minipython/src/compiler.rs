@@ -1,37 +1,380 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::fs;
 use crate::parser;
+use crate::resolver::Resolver;
 use crate::ir;
 use crate::codegen;
+use crate::llvm;
+use crate::wasm;
+use crate::rust;
+use crate::asm;
+use crate::bytecode;
+use crate::vm;
+use crate::interpreter;
+use crate::warnings;
 use std::fs::File;
 use std::io::{BufWriter, Write};
+use std::fmt::{Display, Formatter};
+
+pub use crate::codegen::{CodegenOptions, InputSource, IntegerWidth, OutputFormat, OverflowPolicy, ValueRepresentation};
+pub use crate::ir::DecrementPolicy;
+pub use crate::diagnostic::{Diagnostic, Severity, Label};
+pub use crate::format::format_program;
+pub use crate::warnings::CheckOptions;
+pub use crate::name::NameStore;
+pub use crate::ast::Program;
+pub use crate::ir::IRProgram;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Target {
+    C,
+    //A header and a source file without main()
+    CLib,
+    Llvm,
+    Wat,
+    Asm,
+    Bytecode,
+    //A module to include into Rust code
+    Rust,
+}
+
+impl Target {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Target::C | Target::CLib => "c",
+            Target::Llvm => "ll",
+            Target::Wat => "wat",
+            Target::Asm => "s",
+            Target::Bytecode => "mpyc",
+            Target::Rust => "rs"
+        }
+    }
+}
 
 pub struct CompilerInstance<'a> {
     input_file: &'a Path,
     output_file: &'a Path,
+    target: Target,
+    options: CodegenOptions,
+    checks: CheckOptions,
+    symbol_prefix: String,
+    search_paths: Vec<PathBuf>,
+    prelude: bool,
+    module_files: Vec<PathBuf>,
 }
 
 impl<'a> CompilerInstance<'a> {
-    pub fn new(input_file: &'a Path, output_file: &'a Path) -> Result<CompilerInstance<'a>, String> {
+    pub fn new(input_file: &'a Path, output_file: &'a Path, target: Target, options: CodegenOptions, checks: CheckOptions) -> Result<CompilerInstance<'a>, String> {
+        //Checked before anything is written, emit() does the same for the library API
+        validate_target(target, &options)?;
         if input_file.exists() {
             Ok(CompilerInstance {
                 input_file,
-                output_file
+                output_file,
+                target,
+                options,
+                checks,
+                symbol_prefix: default_symbol_prefix(input_file),
+                search_paths: Vec::new(),
+                prelude: true,
+                module_files: Vec::new()
             })
         } else {
-            Err(String::from(format!("Input file {} does not exist", input_file.display())))
+            Err(format!("Input file {} does not exist", input_file.display()))
+        }
+    }
+
+    //Prepended to the exported symbols of a C library, so they do not clash with other code
+    pub fn with_symbol_prefix(mut self, prefix: &str) -> Result<Self, String> {
+        //The prefix cannot be empty, as the main block would become main()
+        let valid = !prefix.is_empty() && prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') && !prefix.starts_with(|c: char| c.is_ascii_digit());
+        if !valid {
+            return Err(format!("Symbol prefix {} is not a valid C identifier", prefix));
         }
+        self.symbol_prefix = String::from(prefix);
+        Ok(self)
+    }
+
+    //Directories to look for imported modules in, after the directory of the input file
+    pub fn with_search_paths(mut self, search_paths: Vec<PathBuf>) -> Self {
+        self.search_paths = search_paths;
+        self
     }
 
-    pub fn run(&mut self) -> Result<(), String> {
-        let code = fs::read_to_string(self.input_file).map_err(|e| format!("{}", e))?;
-        let (name_store, ast_res) = parser::parse_program(&code);
-        let ast = ast_res?;
-        let ir = ir::convert_program_to_ir(&ast, &name_store)?;
-        let file = File::create(self.output_file).map_err(|e| format!("{}", e))?;
+    //Without the prelude, every called function has to be defined or imported
+    pub fn with_prelude(mut self, prelude: bool) -> Self {
+        self.prelude = prelude;
+        self
+    }
+
+    //The imported module files found by the last run, also if it failed
+    pub fn module_files(&self) -> &[PathBuf] {
+        &self.module_files
+    }
+
+    //Returns the warnings of a successful compilation
+    pub fn run(&mut self) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+        self.module_files.clear();
+        let code = fs::read_to_string(self.input_file).map_err(io_error)?;
+        let (mut name_store, mut ast) = parse(&code)?;
+        let mut resolver = Resolver::new(&self.search_paths).with_prelude(self.prelude);
+        let resolved = resolver.resolve_program(&mut ast, &mut name_store, Some(self.input_file));
+        self.module_files = resolver.module_files().to_vec();
+        resolved?;
+        let mut ir = lower(&ast, &name_store, &self.options)?;
+        let warnings = check(&ir, &name_store, &self.checks)?;
+        optimize(&mut ir);
+        let file = File::create(self.output_file).map_err(io_error)?;
         let mut writer = BufWriter::new(&file);
-        codegen::compile_to_c(&ir, &name_store, &mut writer).map_err(|e| format!("{}", e))?;
-        writer.flush().map_err(|e| format!("{}", e))?;
+        if self.target == Target::CLib {
+            let header_file = self.output_file.with_extension("h");
+            let header_name = header_file.file_name().unwrap().to_string_lossy().into_owned();
+            let file = File::create(&header_file).map_err(io_error)?;
+            let mut header = BufWriter::new(&file);
+            let result = emit_c_lib(&ir, &name_store, &self.options, &self.symbol_prefix, &header_name, &mut header, &mut writer);
+            header.flush().map_err(io_error)?;
+            result?;
+        } else {
+            emit(&ir, &name_store, self.target, &self.options, &mut writer)?;
+        }
+        writer.flush().map_err(io_error)?;
+        Ok(warnings)
+    }
+}
+
+fn validate_target(target: Target, options: &CodegenOptions) -> Result<(), String> {
+    options.validate()?;
+    let program_options = options.input_source != InputSource::Prompt || options.output_format != OutputFormat::NameValue;
+    if *options != CodegenOptions::default() && target != Target::C && target != Target::CLib && target != Target::Rust {
+        Err(String::from("Value representation, integer width, overflow and decrement policy, input source and output format are only supported by the C and Rust backends"))
+    } else if target == Target::Rust && (options.representation == ValueRepresentation::BigNum || program_options) {
+        Err(String::from("The Rust backend does not support arbitrary-precision values, input sources and output formats"))
+    } else if target == Target::Rust && options.width != IntegerWidth::W64 && options.width != IntegerWidth::W128 {
+        Err(String::from("The Rust backend only supports 64 and 128 bit values"))
+    } else if target == Target::CLib && options.representation == ValueRepresentation::BigNum {
+        Err(String::from("Arbitrary-precision values cannot be returned from a C library"))
+    } else if target == Target::CLib && program_options {
+        Err(String::from("Input source and output format only apply to C programs, not to libraries"))
+    } else {
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+//The stages of the compiler, for tools that need the intermediate results.
+//CompilerInstance runs them in this order: parse, resolve, lower, check, optimize and emit.
+pub fn parse(source: &str) -> Result<(NameStore, Program), Vec<Diagnostic>> {
+    let (name_store, ast_res) = parser::parse_program(source);
+    Ok((name_store, ast_res?))
+}
+
+//Adds the functions of imported modules and the used functions of the prelude to the program.
+//Modules are looked up next to the file first, programs without a file only use the search paths.
+pub fn resolve(program: &mut Program, name_store: &mut NameStore, file: Option<&Path>, search_paths: &[PathBuf], prelude: bool) -> Result<(), Vec<Diagnostic>> {
+    Resolver::new(search_paths).with_prelude(prelude).resolve_program(program, name_store, file)
+}
+
+pub fn lower(program: &Program, name_store: &NameStore, options: &CodegenOptions) -> Result<IRProgram, Vec<Diagnostic>> {
+    if !program.imports.is_empty() {
+        return Err(vec![Diagnostic::error("Imports have to be resolved before lowering the program")]);
+    }
+    ir::convert_program_to_ir(program, name_store, options.decrement_policy(), options.overflow)
+}
+
+//Returns the warnings, or all diagnostics if the checks turned one of them into an error
+pub fn check(program: &IRProgram, name_store: &NameStore, checks: &CheckOptions) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    checks.apply(warnings::check_program(program, name_store))
+}
+
+pub fn optimize(program: &mut IRProgram) {
+    ir::optimize_program(program)
+}
+
+//C libraries consist of two files, they are emitted with emit_c_lib
+pub fn emit(program: &IRProgram, name_store: &NameStore, target: Target, options: &CodegenOptions, output: &mut dyn Write) -> Result<(), Vec<Diagnostic>> {
+    validate_target(target, options).map_err(|e| vec![Diagnostic::error(e)])?;
+    match target {
+        Target::C => codegen::compile_to_c(program, name_store, options, output),
+        Target::CLib => Err(Box::from("C libraries need a separate header, use emit_c_lib")),
+        Target::Llvm => llvm::compile_to_llvm(program, name_store, output),
+        Target::Wat => wasm::compile_to_wat(program, name_store, output),
+        Target::Asm => asm::compile_to_asm(program, name_store, output),
+        Target::Bytecode => bytecode::compile_to_bytecode(program, name_store, output),
+        Target::Rust => rust::compile_to_rust(program, name_store, options, output)
+    }.map_err(|e| vec![Diagnostic::error(format!("{}", e))])
+}
+
+//The header is included by the source as header_name
+pub fn emit_c_lib(program: &IRProgram, name_store: &NameStore, options: &CodegenOptions, prefix: &str, header_name: &str, header: &mut dyn Write, output: &mut dyn Write) -> Result<(), Vec<Diagnostic>> {
+    validate_target(Target::CLib, options).map_err(|e| vec![Diagnostic::error(e)])?;
+    codegen::compile_to_c_lib(program, name_store, options, prefix, header_name, header, output)
+        .map_err(|e| vec![Diagnostic::error(format!("{}", e))])
+}
+
+//Compiles source code without touching the file system, with the prelude. Returns the warnings of a successful compilation.
+pub fn compile_source(source: &str, target: Target, options: &CodegenOptions, checks: &CheckOptions, search_paths: &[PathBuf], output: &mut dyn Write) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+    let (mut name_store, mut ast) = parse(source)?;
+    resolve(&mut ast, &mut name_store, None, search_paths, true)?;
+    let mut ir = lower(&ast, &name_store, options)?;
+    let warnings = check(&ir, &name_store, checks)?;
+    optimize(&mut ir);
+    emit(&ir, &name_store, target, options, output)?;
+    Ok(warnings)
+}
+
+//Like compile_source, for the targets generating text
+pub fn compile_to_string(source: &str, target: Target, options: &CodegenOptions, checks: &CheckOptions, search_paths: &[PathBuf]) -> Result<(String, Vec<Diagnostic>), Vec<Diagnostic>> {
+    if target == Target::Bytecode {
+        return Err(vec![Diagnostic::error("Bytecode is binary and cannot be compiled to a string")]);
+    }
+    let mut output = Vec::new();
+    let warnings = compile_source(source, target, options, checks, search_paths, &mut output)?;
+    Ok((String::from_utf8(output).expect("Generated code is not valid UTF-8"), warnings))
+}
+
+//The file name, e.g. exp_ for exp.mpy
+fn default_symbol_prefix(input_file: &Path) -> String {
+    let stem = input_file.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let prefix: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if prefix.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}_", prefix)
+    } else {
+        format!("{}_", prefix)
+    }
+}
+
+fn io_error(e: std::io::Error) -> Vec<Diagnostic> {
+    vec![Diagnostic::error(format!("{}", e))]
+}
+
+//Renders the diagnostics against the current content of the input file, or the imported file they belong to
+pub fn render_diagnostics(input_file: &Path, diagnostics: &[Diagnostic]) -> String {
+    let source = fs::read_to_string(input_file).unwrap_or_default();
+    diagnostics.iter().map(|d| match &d.file {
+        Some(file) => d.render(&file.display().to_string(), &fs::read_to_string(file).unwrap_or_default()),
+        None => d.render(&input_file.display().to_string(), &source)
+    }).collect::<Vec<String>>().join("\n")
+}
+
+pub struct ProgramOutput {
+    pub name: String,
+    pub value: u64,
+}
+
+impl Display for ProgramOutput {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+pub struct InterpreterInstance<'a> {
+    input_file: &'a Path,
+    search_paths: Vec<PathBuf>,
+    prelude: bool,
+}
+
+impl<'a> InterpreterInstance<'a> {
+    pub fn new(input_file: &'a Path) -> Result<InterpreterInstance<'a>, String> {
+        if input_file.exists() {
+            Ok(InterpreterInstance {
+                input_file,
+                search_paths: Vec::new(),
+                prelude: true
+            })
+        } else {
+            Err(format!("Input file {} does not exist", input_file.display()))
+        }
+    }
+
+    pub fn with_search_paths(mut self, search_paths: Vec<PathBuf>) -> Self {
+        self.search_paths = search_paths;
+        self
+    }
+
+    pub fn with_prelude(mut self, prelude: bool) -> Self {
+        self.prelude = prelude;
+        self
+    }
+
+    pub fn run(&mut self, inputs: &[u64]) -> Result<ProgramOutput, Vec<Diagnostic>> {
+        let code = fs::read_to_string(self.input_file).map_err(io_error)?;
+        let (mut name_store, mut ast) = parse(&code)?;
+        resolve(&mut ast, &mut name_store, Some(self.input_file), &self.search_paths, self.prelude)?;
+        let ir = ir::convert_program_to_ir(&ast, &name_store, ir::DecrementPolicy::Wrap, OverflowPolicy::Wrap)?;
+        let value = interpreter::run_program(&ir, &name_store, inputs).map_err(|e| vec![Diagnostic::error(e)])?;
+        Ok(ProgramOutput {
+            name: ir.output.get_name(&name_store).unwrap().clone(),
+            value
+        })
+    }
+}
+
+pub struct VirtualMachineInstance<'a> {
+    bytecode_file: &'a Path,
+}
+
+impl<'a> VirtualMachineInstance<'a> {
+    pub fn new(bytecode_file: &'a Path) -> Result<VirtualMachineInstance<'a>, String> {
+        if bytecode_file.exists() {
+            Ok(VirtualMachineInstance {
+                bytecode_file
+            })
+        } else {
+            Err(format!("Bytecode file {} does not exist", bytecode_file.display()))
+        }
+    }
+
+    pub fn run(&mut self, inputs: &[u64]) -> Result<ProgramOutput, String> {
+        let bytes = fs::read(self.bytecode_file).map_err(|e| format!("{}", e))?;
+        let program = bytecode::BytecodeProgram::from_bytes(&bytes)?;
+        let value = vm::execute(&program, inputs)?;
+        Ok(ProgramOutput {
+            name: program.output_name,
+            value
+        })
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::compiler::{compile_to_string, CompilerInstance, Target, CodegenOptions, CheckOptions};
+    use crate::parser;
+    use std::fs;
+
+    #[test]
+    fn test_reproducible_output() {
+        //Every run writes to its own directory, as file names end up in the output of C libraries
+        let output_dir = std::env::temp_dir().join(format!("minipython-reproducible-{}", std::process::id()));
+        for run in 1..=2 {
+            fs::create_dir_all(output_dir.join(run.to_string())).unwrap();
+        }
+        for entry in fs::read_dir("../examples").unwrap() {
+            let input = entry.unwrap().path();
+            if input.extension().and_then(|e| e.to_str()) != Some("mpy") || parser::is_module(&fs::read_to_string(&input).unwrap()) {
+                continue;
+            }
+            for &target in &[Target::C, Target::CLib, Target::Llvm, Target::Wat, Target::Asm, Target::Bytecode, Target::Rust] {
+                let compile = |run: usize| {
+                    let output = output_dir.join(run.to_string()).join(input.with_extension(target.extension()).file_name().unwrap());
+                    CompilerInstance::new(&input, &output, target, CodegenOptions::default(), CheckOptions::default()).unwrap().run().unwrap();
+                    (fs::read(&output).unwrap(), fs::read(output.with_extension("h")).ok())
+                };
+                assert_eq!(compile(1), compile(2), "{} is not reproducible for {:?}", input.display(), target);
+            }
+        }
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_compile_to_string() {
+        let input = std::path::Path::new("../examples/exp.mpy");
+        let output = std::env::temp_dir().join(format!("minipython-string-{}.c", std::process::id()));
+        CompilerInstance::new(input, &output, Target::C, CodegenOptions::default(), CheckOptions::default()).unwrap().run().unwrap();
+        let source = fs::read_to_string(input).unwrap();
+        let (code, _) = compile_to_string(&source, Target::C, &CodegenOptions::default(), &CheckOptions::default(), &[input.parent().unwrap().to_path_buf()]).unwrap();
+        assert_eq!(code, fs::read_to_string(&output).unwrap());
+        fs::remove_file(&output).unwrap();
+
+        assert!(compile_to_string(&source, Target::Bytecode, &CodegenOptions::default(), &CheckOptions::default(), &[]).is_err());
+        let errors = compile_to_string("input: x\noutput: y\ny=f(x)", Target::C, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::lexer::Span;
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+//Error and warning codes, so tools can match on diagnostics without parsing the message
+pub const INVALID_TOKEN: &str = "E0001";
+pub const UNEXPECTED_TOKEN: &str = "E0002";
+pub const UNEXPECTED_EOF: &str = "E0003";
+pub const UNKNOWN_FUNCTION: &str = "E0004";
+pub const ARGUMENT_COUNT: &str = "E0005";
+pub const MISSING_RETURN: &str = "E0006";
+pub const MODULE_NOT_FOUND: &str = "E0007";
+pub const IMPORT_CYCLE: &str = "E0008";
+pub const UNKNOWN_IMPORT: &str = "E0009";
+pub const IMPORT_CONFLICT: &str = "E0010";
+pub const UNUSED_VARIABLE: &str = "W0001";
+pub const UNINITIALIZED_READ: &str = "W0002";
+pub const DEAD_STORE: &str = "W0003";
+pub const UNUSED_FUNCTION: &str = "W0004";
+pub const UNREACHABLE_CODE: &str = "W0005";
+pub const DEAD_LOOP: &str = "W0006";
+pub const RECURSION: &str = "W0007";
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning")
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+//Diagnostics without a primary label are not tied to the source, e.g. I/O errors
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub primary: Option<Label>,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    //Set for diagnostics in imported files, the others belong to the compiled file
+    pub file: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: None,
+            message: message.into(),
+            primary: None,
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            ..Diagnostic::error(message)
+        }
+    }
+
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_primary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.primary = Some(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, message: message.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_file(mut self, file: impl Into<PathBuf>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    fn header(&self) -> String {
+        match self.code {
+            Some(code) => format!("{}[{}]: {}", self.severity, code, self.message),
+            None => format!("{}: {}", self.severity, self.message)
+        }
+    }
+
+    //Renders the diagnostic like rustc does, with the affected source lines and carets below them
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let mut out = format!("{}\n", self.header());
+        let primary = match &self.primary {
+            Some(primary) => primary,
+            None => {
+                for note in &self.notes {
+                    out.push_str(&format!("  = note: {}\n", note));
+                }
+                return out;
+            }
+        };
+
+        let mut labels: Vec<(&Label, char)> = vec![(primary, '^')];
+        labels.extend(self.secondary.iter().map(|l| (l, '-')));
+        labels.sort_by_key(|(l, _)| (l.span.start.line, l.span.start.col));
+        let lines: Vec<&str> = source.lines().collect();
+        let width = labels.iter().map(|(l, _)| l.span.start.line).max().unwrap_or(1).to_string().len();
+        let gutter = " ".repeat(width);
+
+        out.push_str(&format!("{}--> {}:{}\n", gutter, file_name, primary.span));
+        out.push_str(&format!("{} |\n", gutter));
+        let mut last_line = None;
+        for (label, marker) in labels {
+            let line = label.span.start.line;
+            let text = lines.get(line.wrapping_sub(1)).copied().unwrap_or("");
+            if last_line != Some(line) {
+                if let Some(last) = last_line {
+                    if line > last + 1 {
+                        out.push_str("...\n");
+                    }
+                }
+                out.push_str(&format!("{:>width$} | {}\n", line, text, width = width));
+            }
+            last_line = Some(line);
+
+            //Spans covering several lines are only underlined on their first line
+            let start = label.span.start.col.max(1);
+            let end = if label.span.end.line == line {
+                label.span.end.col
+            } else {
+                text.chars().count() + 1
+            };
+            let len = end.saturating_sub(start).max(1);
+            let underline = format!("{}{}", " ".repeat(start - 1), marker.to_string().repeat(len));
+            if label.message.is_empty() {
+                out.push_str(&format!("{} | {}\n", gutter, underline));
+            } else {
+                out.push_str(&format!("{} | {} {}\n", gutter, underline, label.message));
+            }
+        }
+        out.push_str(&format!("{} |\n", gutter));
+        for note in &self.notes {
+            out.push_str(&format!("{} = note: {}\n", gutter, note));
+        }
+        out
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.primary {
+            Some(primary) => write!(f, "{}: {}", primary.span, self.header()),
+            None => write!(f, "{}", self.header())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostic::{Diagnostic, ARGUMENT_COUNT};
+    use crate::lexer::{Location, Span};
+
+    fn span(line: usize, start: usize, end: usize) -> Span {
+        Span::new(Location { line, col: start, pos: 0 }, Location { line, col: end, pos: 0 })
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "input: x
+output: y
+def f(a):
+    return a
+y=f(x, x)";
+        let diagnostic = Diagnostic::error("Function f requires 1 arguments, but got 2")
+            .with_code(ARGUMENT_COUNT)
+            .with_primary(span(5, 1, 10), "called with 2 arguments")
+            .with_secondary(span(3, 1, 10), "function defined here")
+            .with_note("arguments are passed by value");
+        let expected = "error[E0005]: Function f requires 1 arguments, but got 2
+ --> test.mpy:5:1
+  |
+3 | def f(a):
+  | --------- function defined here
+...
+5 | y=f(x, x)
+  | ^^^^^^^^^ called with 2 arguments
+  |
+  = note: arguments are passed by value
+";
+        assert_eq!(diagnostic.render("test.mpy", source), expected);
+        assert_eq!(format!("{}", diagnostic), "5:1: error[E0005]: Function f requires 1 arguments, but got 2");
+    }
+
+    #[test]
+    fn test_render_without_span() {
+        let diagnostic = Diagnostic::error("No such file");
+        assert_eq!(diagnostic.render("test.mpy", ""), "error: No such file\n");
+    }
+}
@@ -0,0 +1,293 @@
+use crate::ast::*;
+use crate::name::*;
+use crate::parser;
+use crate::lexer::{Lexer, Token, Comment};
+use crate::diagnostic::Diagnostic;
+use std::collections::VecDeque;
+
+const INDENT: &str = "    ";
+
+struct Formatter<'a> {
+    name_store: &'a NameStore,
+    comments: VecDeque<Comment>,
+    output: String,
+}
+
+impl<'a> Formatter<'a> {
+    fn name(&self, name: InternedName) -> &str {
+        self.name_store.get(name).unwrap()
+    }
+
+    fn names(&self, names: &[InternedName]) -> String {
+        names.iter().map(|&n| self.name(n)).collect::<Vec<&str>>().join(", ")
+    }
+
+    //Own-line comments keep their depth, rounded down to whole indentation levels. The lexer ignores
+    //the indentation of comments, so this cannot change the blocks.
+    fn comments_before(&mut self, line: usize) {
+        while let Some(comment) = self.comments.front() {
+            if comment.span.start.line >= line {
+                break;
+            }
+            let depth = (comment.span.start.col - 1) / INDENT.len();
+            let text = format!("{}#{}\n", INDENT.repeat(depth), comment.text);
+            self.output.push_str(&text);
+            self.comments.pop_front();
+        }
+    }
+
+    //Comments directly following the last line of a function belong to it, like #enddef
+    fn comments_after(&mut self, mut line: usize) {
+        while let Some(comment) = self.comments.front() {
+            if comment.trailing || comment.span.start.line != line + 1 {
+                break;
+            }
+            line += 1;
+            self.comments_before(line + 1);
+        }
+    }
+
+    fn line(&mut self, depth: usize, content: &str, line: usize) {
+        self.comments_before(line);
+        self.output.push_str(&INDENT.repeat(depth));
+        self.output.push_str(content);
+        if let Some(comment) = self.comments.front() {
+            if comment.trailing && comment.span.start.line == line {
+                let text = format!(" #{}", comment.text);
+                self.output.push_str(&text);
+                self.comments.pop_front();
+            }
+        }
+        self.output.push('\n');
+    }
+
+    fn statement(&mut self, statement: &Ast, depth: usize) {
+        let line = statement.span().start.line;
+        match statement {
+            Ast::Incr(name, _) => self.line(depth, &format!("{}+=1", self.name(*name)), line),
+            Ast::Decr(name, _) => self.line(depth, &format!("{}-=1", self.name(*name)), line),
+            Ast::Return(name, _) => self.line(depth, &format!("return {}", self.name(*name)), line),
+            Ast::Assign { var_name, fun_name, args, .. } => {
+                let content = format!("{}={}({})", self.name(*var_name), self.name(*fun_name), self.names(args));
+                self.line(depth, &content, line)
+            },
+            Ast::While { cond_var, body, .. } => {
+                self.line(depth, &format!("while {}!=0:", self.name(*cond_var)), line);
+                self.block(body, depth + 1);
+            },
+            Ast::Def { name, parameters, body, .. } => {
+                let content = format!("def {}({}):", self.name(*name), self.names(parameters));
+                self.line(depth, &content, line);
+                self.block(body, depth + 1);
+            }
+        }
+    }
+
+    fn block(&mut self, body: &[Ast], depth: usize) {
+        for statement in body {
+            self.statement(statement, depth);
+        }
+    }
+
+    //Followed by a blank line, if there are any imports
+    fn imports(&mut self, imports: &[Import]) {
+        for import in imports {
+            let content = match &import.functions {
+                None => format!("import {}", self.name(import.module)),
+                Some(functions) => format!("from {} import {}", self.name(import.module), self.names(functions))
+            };
+            self.line(0, &content, import.span.start.line);
+        }
+        if !imports.is_empty() {
+            self.output.push('\n');
+        }
+    }
+
+    fn top_level(&mut self, body: &[Ast]) {
+        let mut after_function = false;
+        for (i, statement) in body.iter().enumerate() {
+            let is_function = matches!(statement, Ast::Def { .. });
+            if (is_function || after_function) && i > 0 {
+                self.output.push('\n');
+            }
+            self.statement(statement, 0);
+            if is_function {
+                self.comments_after(statement.span().end.line);
+            }
+            after_function = is_function;
+        }
+        self.comments_before(usize::MAX);
+    }
+}
+
+fn format_module(code: &str) -> Result<String, Vec<Diagnostic>> {
+    let (name_store, module) = parser::parse_module(code);
+    let module = module?;
+
+    let mut lexer = Lexer::new(code).with_comments();
+    lexer.by_ref().for_each(drop);
+    let mut formatter = Formatter {
+        name_store: &name_store,
+        comments: lexer.take_comments().into_iter().collect(),
+        output: String::new(),
+    };
+    formatter.imports(&module.imports);
+    formatter.top_level(&module.functions);
+    Ok(formatter.output)
+}
+
+//Formats a program or module in the canonical style: no spaces around operators, four spaces of indentation
+//and functions separated by blank lines. Comments are kept.
+pub fn format_program(code: &str) -> Result<String, Vec<Diagnostic>> {
+    if parser::is_module(code) {
+        return format_module(code);
+    }
+    let (name_store, program) = parser::parse_program(code);
+    let program = program?;
+
+    let mut lexer = Lexer::new(code).with_comments();
+    let tokens: Vec<(usize, Token)> = lexer.by_ref().filter_map(Result::ok).map(|(l, token, _)| (l.line, token)).collect();
+    let header_line = |header: Token| tokens.iter().find(|(_, t)| *t == header).map(|(line, _)| *line).unwrap_or(0);
+    let input_line = header_line(Token::Input);
+    let output_line = header_line(Token::Output);
+
+    let mut formatter = Formatter {
+        name_store: &name_store,
+        comments: lexer.take_comments().into_iter().collect(),
+        output: String::new(),
+    };
+
+    formatter.imports(&program.imports);
+    let inputs = format!("input: {}", formatter.names(&program.inputs));
+    formatter.line(0, inputs.trim_end(), input_line);
+    let output = format!("output: {}", formatter.name(program.output));
+    formatter.line(0, &output, output_line);
+    formatter.output.push('\n');
+    formatter.top_level(&program.body);
+
+    Ok(formatter.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::format_program;
+
+    #[test]
+    fn test_format() {
+        let code =
+            "#Header
+input:a,b # inputs
+output:  x
+def add(x,y):
+    while x != 0:
+        n += 1
+        x -=1
+    #endwhile
+    return n
+#enddef
+x = add( a ,b )";
+        let expected =
+            "#Header
+input: a, b # inputs
+output: x
+
+def add(x, y):
+    while x!=0:
+        n+=1
+        x-=1
+    #endwhile
+    return n
+#enddef
+
+x=add(a, b)
+";
+        let formatted = format_program(code).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_program(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_indented_comments() {
+        let code =
+            "input: a
+output: x
+def f(a):
+    while a!=0:
+      #between levels
+        a-=1
+#start of line
+        x+=1
+    return x
+x=f(a)
+";
+        let expected =
+            "input: a
+output: x
+
+def f(a):
+    while a!=0:
+    #between levels
+        a-=1
+#start of line
+        x+=1
+    return x
+
+x=f(a)
+";
+        let formatted = format_program(code).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format_program(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_imports() {
+        let code =
+            "from  math import add,mul
+import util
+input: a
+output: x
+x = mul(a, a)";
+        let expected =
+            "from math import add, mul
+import util
+
+input: a
+output: x
+
+x=mul(a, a)
+";
+        assert_eq!(format_program(code).unwrap(), expected);
+
+        let module =
+            "import util
+def double(a):
+    b = add(a, a)
+    return b
+def one(a):
+    a+=1
+    return a";
+        let expected =
+            "import util
+
+def double(a):
+    b=add(a, a)
+    return b
+
+def one(a):
+    a+=1
+    return a
+";
+        assert_eq!(format_program(module).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_format_examples() {
+        for example in &["add", "mul", "exp", "arith", "div"] {
+            let code = std::fs::read_to_string(format!("../examples/{}.mpy", example)).unwrap();
+            let formatted = format_program(&code).unwrap();
+            assert_eq!(format_program(&formatted).unwrap(), formatted);
+            let comments = |code: &str| code.lines().filter(|l| l.trim_start().starts_with('#')).count();
+            assert_eq!(comments(&formatted), comments(&code));
+        }
+    }
+}
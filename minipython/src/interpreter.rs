@@ -0,0 +1,241 @@
+use crate::ir::*;
+use crate::name::*;
+use crate::value::Value;
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+use std::collections::HashMap;
+
+//Same limit as the bytecode VM. Frames live on the heap, so deep recursion does not overflow the native stack.
+const MAX_CALL_DEPTH: usize = 1_000_000;
+
+//A block that is being executed. Loop bodies are repeated while their condition holds.
+struct Block<'a> {
+    statements: &'a [IRStatement],
+    next: usize,
+    condition_var: Option<Value>,
+}
+
+impl<'a> Block<'a> {
+    fn new(statements: &'a [IRStatement], condition_var: Option<Value>) -> Self {
+        Block {
+            statements,
+            next: 0,
+            condition_var
+        }
+    }
+}
+
+//Values are stored like in the generated C code: 64-bit unsigned integers with wrapping arithmetic
+struct Frame<'a> {
+    values: HashMap<Value, u64>,
+    blocks: Vec<Block<'a>>,
+    //The function and the value of the caller that receives the result, None for the main block
+    call: Option<(InternedName, Value)>,
+}
+
+impl<'a> Frame<'a> {
+    fn new(body: &'a [IRStatement], call: Option<(InternedName, Value)>) -> Self {
+        Frame {
+            values: HashMap::new(),
+            blocks: vec![Block::new(body, None)],
+            call
+        }
+    }
+
+    fn get(&self, val: Value) -> u64 {
+        //Every value is initialized to 0
+        *self.values.get(&val).unwrap_or(&0)
+    }
+
+    fn set(&mut self, val: Value, content: u64) {
+        self.values.insert(val, content);
+    }
+
+    //The statement to execute next, or None if the frame has run out of statements
+    fn next_statement(&mut self) -> Option<&'a IRStatement> {
+        loop {
+            let block = self.blocks.last_mut()?;
+            let statements = block.statements;
+            if let Some(statement) = statements.get(block.next) {
+                block.next += 1;
+                return Some(statement);
+            }
+            let condition_var = block.condition_var;
+            if condition_var.is_some_and(|v| self.get(v) != 0) {
+                self.blocks.last_mut().unwrap().next = 0;
+            } else {
+                self.blocks.pop();
+            }
+        }
+    }
+}
+
+fn execute<'a>(program: &'a IRProgram, name_store: &NameStore, main: Frame<'a>) -> Result<Frame<'a>, String> {
+    let mut frames = vec![main];
+    loop {
+        let depth = frames.len();
+        let frame = frames.last_mut().unwrap();
+        let statement = match frame.next_statement() {
+            Some(statement) => statement,
+            None => {
+                return match frame.call {
+                    Some((func, _)) => Err(format!("Function {} did not return a value", name_store.get(func).unwrap())),
+                    None => Ok(frames.pop().unwrap())
+                };
+            }
+        };
+        match statement {
+            ValueModify(val, change, _) => {
+                let old = frame.get(*val);
+                frame.set(*val, old.wrapping_add(*change as u64));
+            },
+            Return(val, _) => {
+                let result = frame.get(*val);
+                let (_, target) = frame.call.ok_or_else(|| String::from("Unexpected return in main block"))?;
+                frames.pop();
+                frames.last_mut().unwrap().set(target, result);
+            },
+            Loop { condition_var, body, .. } => {
+                if frame.get(*condition_var) != 0 {
+                    frame.blocks.push(Block::new(body, Some(*condition_var)));
+                }
+            },
+            FunctionCall { func, args, target, .. } => {
+                let function = program.function(*func).ok_or_else(|| format!("Function {} does not exist", name_store.get(*func).unwrap()))?;
+                if depth >= MAX_CALL_DEPTH {
+                    return Err(String::from("Call stack overflow"));
+                }
+                let mut callee = Frame::new(&function.body.body, Some((*func, *target)));
+                for (&param, &arg) in function.params.iter().zip(args) {
+                    callee.set(param, frame.get(arg));
+                }
+                frames.push(callee);
+            }
+        }
+    }
+}
+
+pub fn run_program(program: &IRProgram, name_store: &NameStore, inputs: &[u64]) -> Result<u64, String> {
+    if program.inputs.len() != inputs.len() {
+        return Err(format!("Program requires {} inputs, but got {}", program.inputs.len(), inputs.len()));
+    }
+
+    let mut frame = Frame::new(&program.main.body, None);
+
+    for (&input_val, &input) in program.inputs.iter().zip(inputs) {
+        frame.set(input_val, input);
+    }
+
+    let frame = execute(program, name_store, frame)?;
+    Ok(frame.get(program.output))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interpreter::run_program;
+    use crate::ir::{convert_program_to_ir, DecrementPolicy};
+    use crate::codegen::OverflowPolicy;
+    use crate::parser::parse_program;
+
+    fn run_code(code: &str, inputs: &[u64]) -> Result<u64, String> {
+        let (store, ast_res) = parse_program(code);
+        let ir = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        run_program(&ir, &store, inputs)
+    }
+
+    const ARITHMETIC: &str =
+        "input: x1, x2
+output: a
+
+def add(x, y):
+    while x!=0:
+        n+=1
+        x-=1
+    while y!=0:
+        n+=1
+        y-=1
+    return n
+
+def mul(a, b):
+    while b!=0:
+        n=add(n, a)
+        b-=1
+    return n
+
+def exp(a, b):
+    n+=1
+    while b!=0:
+        n=mul(n, a)
+        b-=1
+    return n
+
+a=exp(x1, x2)";
+
+    #[test]
+    fn test_run_loop() {
+        let code =
+            "input: x, y
+output: z
+while x!=0:
+    x-=1
+    z+=1
+while y!=0:
+    y-=1
+    z+=1
+";
+        assert_eq!(run_code(code, &[3, 4]), Ok(7));
+        assert_eq!(run_code(code, &[0, 0]), Ok(0));
+    }
+
+    #[test]
+    fn test_run_functions() {
+        assert_eq!(run_code(ARITHMETIC, &[2, 10]), Ok(1024));
+        assert_eq!(run_code(ARITHMETIC, &[3, 0]), Ok(1));
+    }
+
+    #[test]
+    fn test_run_return_in_loop() {
+        let code =
+            "input: x
+output: y
+def first(a):
+    while a!=0:
+        return a
+    return a
+y=first(x)";
+        assert_eq!(run_code(code, &[5]), Ok(5));
+    }
+
+    #[test]
+    fn test_run_wrapping_decrement() {
+        let code =
+            "input:
+output: y
+y-=1";
+        assert_eq!(run_code(code, &[]), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_run_wrong_input_count() {
+        assert!(run_code(ARITHMETIC, &[1]).is_err());
+    }
+
+    #[test]
+    fn test_run_deep_recursion() {
+        let code =
+            "input: x
+output: y
+def down(a):
+    while a!=0:
+        a-=1
+        a=down(a)
+        a+=1
+        return a
+    #endwhile
+    return a
+#enddef
+y=down(x)";
+        assert_eq!(run_code(code, &[100_000]), Ok(100_000));
+        let endless = "input: x\noutput: y\ndef f(a):\n    a=f(a)\n    return a\n#enddef\ny=f(x)";
+        assert_eq!(run_code(endless, &[1]), Err(String::from("Call stack overflow")));
+    }
+}
@@ -2,7 +2,10 @@ use crate::value::Value;
 use crate::name::*;
 use crate::ast::*;
 use crate::ast::Ast::*;
-use std::collections::{HashMap, BTreeMap};
+use crate::lexer::Span;
+use crate::diagnostic::{Diagnostic, ARGUMENT_COUNT, UNKNOWN_FUNCTION, MISSING_RETURN};
+use crate::codegen::OverflowPolicy;
+use std::collections::BTreeMap;
 use crate::ir::IRStatement::{ValueModify, FunctionCall, Loop};
 use std::rc::Rc;
 use std::ops::Deref;
@@ -13,6 +16,9 @@ use std::cell::RefCell;
 pub struct IRFunction {
     pub params: Vec<Value>,
     pub body: IRBlock,
+    //The span is in another file for imported functions
+    pub span: Span,
+    pub imported: bool,
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -23,27 +29,55 @@ pub struct IRBlock {
 
 #[derive(Debug, Eq, PartialEq)]
 pub enum IRStatement {
-    ValueModify(Value, i64),
+    //Merged modifications span all statements they were created from
+    ValueModify(Value, i64, Span),
     FunctionCall {
         func: InternedName,
         args: Vec<Value>,
         target: Value,
+        span: Span,
     },
     Loop {
         condition_var: Value,
         body: Vec<IRStatement>,
+        span: Span,
     },
-    Return(Value),
+    Return(Value, Span),
+}
+
+impl IRStatement {
+    pub fn span(&self) -> Span {
+        match self {
+            ValueModify(_, _, span) => *span,
+            FunctionCall { span, .. } => *span,
+            Loop { span, .. } => *span,
+            IRStatement::Return(_, span) => *span
+        }
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct IRProgram {
     pub inputs: Vec<Value>,
     pub output: Value,
-    pub functions: HashMap<InternedName, IRFunction>,
+    //In source order, so everything generated from the program is deterministic
+    pub functions: Vec<(InternedName, IRFunction)>,
     pub main: IRBlock,
 }
 
+impl IRProgram {
+    pub fn function(&self, name: InternedName) -> Option<&IRFunction> {
+        self.functions.iter().find(|(n, _)| *n == name).map(|(_, function)| function)
+    }
+}
+
+//What happens when a value of zero is decremented
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DecrementPolicy {
+    Wrap,
+    Saturate,
+}
+
 #[derive(Eq, PartialEq, Clone, Copy, Debug)]
 enum ValueKind {
     IO,
@@ -54,14 +88,18 @@ struct Context {
     next_id: Rc<RefCell<u64>>,
     context: BTreeMap<InternedName, (ValueKind, Value)>,
     function_calls: Vec<Ast>,
+    decrement: DecrementPolicy,
+    overflow: OverflowPolicy,
 }
 
 impl Context {
-    fn root() -> Self {
+    fn root(decrement: DecrementPolicy, overflow: OverflowPolicy) -> Self {
         Context {
             next_id: Rc::new(RefCell::new(0)),
             context: BTreeMap::new(),
-            function_calls: Vec::new()
+            function_calls: Vec::new(),
+            decrement,
+            overflow
         }
     }
 
@@ -69,7 +107,9 @@ impl Context {
         Context {
             next_id: self.next_id.clone(),
             context: BTreeMap::new(),
-            function_calls: Vec::new()
+            function_calls: Vec::new(),
+            decrement: self.decrement,
+            overflow: self.overflow
         }
     }
 
@@ -106,46 +146,62 @@ impl Context {
     }
 }
 
+//Collects consecutive modifications of values, so they can be merged into one statement.
+//Increments and decrements only cancel out if both of them wrap around: with saturating decrements 0 - 1 + 1 = 1,
+//with saturating increments MAX + 1 - 1 = MAX - 1 and a trapping increment has to trap even if a decrement follows.
+//So every value has a list of modifications that are applied in order.
 struct OptimizationContext {
-    values: BTreeMap<Value, i64>
+    values: BTreeMap<Value, Vec<(i64, Span)>>,
+    wrapping: bool,
 }
 
 impl OptimizationContext {
-    fn new() -> Self {
+    fn new(decrement: DecrementPolicy, overflow: OverflowPolicy) -> Self {
         OptimizationContext {
-            values: BTreeMap::new()
+            values: BTreeMap::new(),
+            wrapping: decrement == DecrementPolicy::Wrap && overflow == OverflowPolicy::Wrap
+        }
+    }
+
+    fn modify(&mut self, v: Value, change: i64, span: Span) {
+        let wrapping = self.wrapping;
+        let modifications = self.values.entry(v).or_default();
+        match modifications.last_mut() {
+            Some((last, last_span)) if wrapping || *last == 0 || (*last > 0) == (change > 0) => {
+                *last += change;
+                *last_span = last_span.merge(span);
+            },
+            _ => modifications.push((change, span))
         }
     }
 
-    fn incr(&mut self, v: Value) {
-        let old = self.values.entry(v).or_insert(0);
-        *old += 1;
+    fn incr(&mut self, v: Value, span: Span) {
+        self.modify(v, 1, span);
+    }
+
+    fn decr(&mut self, v: Value, span: Span) {
+        self.modify(v, -1, span);
     }
 
-    fn decr(&mut self, v: Value) {
-        let old = self.values.entry(v).or_insert(0);
-        *old -= 1;
+    fn push_modifications(target: &mut Vec<IRStatement>, val: Value, modifications: &[(i64, Span)]) {
+        for &(m, span) in modifications {
+            if m != 0 {
+                target.push(ValueModify(val, m, span));
+            }
+        }
     }
 
     fn flush(&mut self, target: &mut Vec<IRStatement>, variables: &[Value]) {
         for val in variables {
-            match self.values.get(val) {
-                None => (),
-                Some(&m) => {
-                    self.values.remove(val);
-                    if m != 0 {
-                        target.push(ValueModify(*val, m));
-                    }
-                }
+            if let Some(modifications) = self.values.remove(val) {
+                Self::push_modifications(target, *val, &modifications);
             }
         }
     }
 
     fn flush_all(&mut self, target: &mut Vec<IRStatement>) {
-        for (&val, &modification) in &self.values {
-            if modification != 0 {
-                target.push(ValueModify(val, modification));
-            }
+        for (&val, modifications) in &self.values {
+            Self::push_modifications(target, val, modifications);
         }
         self.values.clear();
     }
@@ -153,24 +209,24 @@ impl OptimizationContext {
 
 fn convert_statements(ctx: &mut Context, statements: &Vec<Ast>) -> Vec<IRStatement> {
     let mut ir = Vec::new();
-    let mut opt = OptimizationContext::new();
+    let mut opt = OptimizationContext::new(ctx.decrement, ctx.overflow);
 
     for statement in statements {
         match statement {
-            Incr(name) => {
+            Incr(name, span) => {
                 let v = ctx.lookup_or_create(name);
-                opt.incr(v);
+                opt.incr(v, *span);
             }
-            Decr(name) => {
+            Decr(name, span) => {
                 let v = ctx.lookup_or_create(name);
-                opt.decr(v);
+                opt.decr(v, *span);
             }
-            Return(name) => {
+            Return(name, span) => {
                 opt.flush_all(&mut ir);
                 let v = ctx.lookup_or_create(name);
-                ir.push(IRStatement::Return(v));
+                ir.push(IRStatement::Return(v, *span));
             }
-            Assign { var_name, fun_name, args } => {
+            Assign { var_name, fun_name, args, span } => {
                 //Record function call for checking it later
                 ctx.function_calls.push(statement.clone());
                 let args_values: Vec<Value> = args.iter().map(|n| ctx.lookup_or_create(n)).collect();
@@ -181,15 +237,17 @@ fn convert_statements(ctx: &mut Context, statements: &Vec<Ast>) -> Vec<IRStateme
                     func: *fun_name,
                     args: args_values,
                     target,
+                    span: *span,
                 });
             }
-            While { cond_var, body } => {
+            While { cond_var, body, span } => {
                 let cond_val = ctx.lookup_or_create(cond_var);
                 opt.flush_all(&mut ir);
                 let ir_body = convert_statements(ctx, body);
                 ir.push(Loop {
                     condition_var: cond_val,
                     body: ir_body,
+                    span: *span,
                 });
             }
             _ => panic!("Unexpected statement")
@@ -200,42 +258,41 @@ fn convert_statements(ctx: &mut Context, statements: &Vec<Ast>) -> Vec<IRStateme
     ir
 }
 
-fn convert_block(ctx: &mut Context, statements: &Vec<Ast>, check_return: bool) -> Result<IRBlock, String> {
+fn convert_block(ctx: &mut Context, statements: &Vec<Ast>) -> IRBlock {
     let ir_statements = convert_statements(ctx, statements);
-    let has_return = !check_return || ir_statements.iter().any(|st| match st {
-        IRStatement::Return(_) => true,
-        _ => false
-    });
-    if has_return {
-        Ok(IRBlock {
-            values: ctx.get_context_values(),
-            body: ir_statements,
-        })
-    } else {
-        Err(String::from("Block has no return value"))
+    IRBlock {
+        values: ctx.get_context_values(),
+        body: ir_statements,
     }
 }
 
-fn convert_function(ctx: &mut Context, parameters: &Vec<InternedName>, body: &Vec<Ast>) -> Result<IRFunction, String> {
+fn convert_function(ctx: &mut Context, parameters: &[InternedName], body: &Vec<Ast>, span: Span, imported: bool) -> IRFunction {
     let mut func_ctx = ctx.create_subcontext();
     let func = IRFunction {
         params: parameters.iter().map(|&n| func_ctx.new_io_value(n)).collect(),
-        body: convert_block(&mut func_ctx, body, true)?,
+        body: convert_block(&mut func_ctx, body),
+        span,
+        imported,
     };
     ctx.function_calls.append(&mut func_ctx.function_calls);
-    Ok(func)
+    func
 }
 
-fn convert_program(ctx: &mut Context, program: &Program) -> Result<IRProgram, String> {
+fn convert_program(ctx: &mut Context, program: &Program) -> IRProgram {
     let inputs: Vec<Value> = program.inputs.iter().map(|n| ctx.new_io_value(*n)).collect();
     let output = ctx.new_io_value(program.output);
-    let mut functions = HashMap::new();
+    let mut functions: Vec<(InternedName, IRFunction)> = Vec::new();
     let mut statements: Vec<Ast> = Vec::new();
 
     for expr in &program.body {
         match expr {
-            Def { name, parameters, body } => {
-                functions.insert(*name, convert_function(ctx, parameters, body)?);
+            Def { name, parameters, body, span } => {
+                let function = convert_function(ctx, parameters, body, *span, program.imported.contains(name));
+                //A later definition replaces an earlier one with the same name
+                match functions.iter_mut().find(|(n, _)| n == name) {
+                    Some(existing) => existing.1 = function,
+                    None => functions.push((*name, function))
+                }
             }
             _ => {
                 statements.push(expr.clone())
@@ -243,44 +300,91 @@ fn convert_program(ctx: &mut Context, program: &Program) -> Result<IRProgram, St
         }
     }
 
-    let block = convert_block(ctx, &statements, false)?;
+    let block = convert_block(ctx, &statements);
 
-    Ok(IRProgram {
+    IRProgram {
         inputs,
         output,
         functions,
         main: block,
-    })
+    }
 }
 
-pub fn convert_program_to_ir(program: &Program, name_store: &NameStore) -> Result<IRProgram, String> {
-    let mut ctx = Context::root();
-    let ir_prog = convert_program(&mut ctx, program)?;
-
-    let func_calls = ctx.function_calls;
-
-    for x in func_calls {
-        match x {
-            Assign { var_name, fun_name, args } => {
-                let func = ir_prog.functions.get(&fun_name);
-                let f = name_store.get(fun_name).unwrap();
-                let v = name_store.get(var_name).unwrap();
-                match func {
-                    Some(func) => {
-                        if func.params.len() != args.len() {
-                            return Err(format!("Error assigning to variable {}: Function {} requires {} arguments, but got {}", v, f, func.params.len(), args.len()))
-                        }
-                    },
-                    None => {
-                        return Err(format!("Error assigning to variable {}: Function {} does not exist", v, f))
-                    }
-                }
-            },
-            _ => unreachable!()
+fn check_function_call(ir_prog: &IRProgram, name_store: &NameStore, call: &Ast) -> Option<Diagnostic> {
+    match call {
+        Assign { var_name, fun_name, args, span } => {
+            let f = name_store.get(*fun_name).unwrap();
+            let v = name_store.get(*var_name).unwrap();
+            match ir_prog.function(*fun_name) {
+                Some(func) if func.params.len() != args.len() => {
+                    let diagnostic = Diagnostic::error(format!("Error assigning to variable {}: Function {} requires {} arguments, but got {}", v, f, func.params.len(), args.len()))
+                        .with_code(ARGUMENT_COUNT)
+                        .with_primary(*span, format!("called with {} arguments", args.len()));
+                    //The definition of an imported function is in another file
+                    Some(if func.imported {
+                        diagnostic.with_note(format!("{} is imported", f))
+                    } else {
+                        diagnostic.with_secondary(func.span, format!("{} is defined here", f))
+                    })
+                },
+                Some(_) => None,
+                None => Some(
+                    Diagnostic::error(format!("Error assigning to variable {}: Function {} does not exist", v, f))
+                        .with_code(UNKNOWN_FUNCTION)
+                        .with_primary(*span, "")
+                        .with_note("functions have to be defined at the top level with `def` or imported")
+                )
+            }
+        },
+        _ => unreachable!()
+    }
+}
+
+//The policies decide which modifications of values can be merged, they have to match the ones of the backend
+pub fn convert_program_to_ir(program: &Program, name_store: &NameStore, decrement: DecrementPolicy, overflow: OverflowPolicy) -> Result<IRProgram, Vec<Diagnostic>> {
+    let mut ctx = Context::root(decrement, overflow);
+    let ir_prog = convert_program(&mut ctx, program);
+    let mut diagnostics = Vec::new();
+
+    for (name, func) in &ir_prog.functions {
+        if !func.body.body.iter().any(|st| matches!(st, IRStatement::Return(..))) {
+            diagnostics.push(Diagnostic::error(format!("Function {} has no return value", name_store.get(*name).unwrap()))
+                .with_code(MISSING_RETURN)
+                .with_primary(func.span, "")
+                .with_note("the body of a function needs a `return` statement outside of loops"));
+        }
+    }
+
+    for call in &ctx.function_calls {
+        diagnostics.extend(check_function_call(&ir_prog, name_store, call));
+    }
+
+    if diagnostics.is_empty() {
+        Ok(ir_prog)
+    } else {
+        diagnostics.sort_by_key(|d| d.primary.as_ref().map(|l| l.span.start.pos));
+        Err(diagnostics)
+    }
+}
+
+//Statements after a return are never executed. Loops are kept, as they might not run at all.
+fn remove_unreachable(statements: &mut Vec<IRStatement>) {
+    if let Some(position) = statements.iter().position(|st| matches!(st, IRStatement::Return(..))) {
+        statements.truncate(position + 1);
+    }
+    for statement in statements {
+        if let Loop { body, .. } = statement {
+            remove_unreachable(body);
         }
     }
+}
 
-    Ok(ir_prog)
+//Modifications are already merged during the conversion, this removes the code it cannot see is dead
+pub fn optimize_program(program: &mut IRProgram) {
+    for (_, function) in &mut program.functions {
+        remove_unreachable(&mut function.body.body);
+    }
+    remove_unreachable(&mut program.main.body);
 }
 
 #[cfg(test)]
@@ -288,11 +392,12 @@ mod tests {
     use crate::ast::Program;
     use crate::name::NameStore;
     use crate::ast::Ast::{While, Decr, Incr, Def, Return, Assign};
-    use crate::ir::{convert_program_to_ir, IRProgram, IRBlock, IRFunction, IRStatement, Context, convert_program};
+    use crate::ir::{convert_program_to_ir, optimize_program, IRProgram, IRBlock, IRFunction, IRStatement, Context, convert_program, DecrementPolicy};
+    use crate::codegen::OverflowPolicy;
     use crate::value::Value;
-    use std::collections::HashMap;
     use crate::ir::IRStatement::{ValueModify, Loop, FunctionCall};
     use crate::parser::parse_program;
+    use crate::lexer::Span;
 
     #[test]
     fn test_function_call_collection() {
@@ -317,7 +422,7 @@ f=mul(d, e)";
 
         let (store, ast_res) = parse_program(code);
         let ast = ast_res.unwrap();
-        let mut ctx = Context::root();
+        let mut ctx = Context::root(DecrementPolicy::Wrap, OverflowPolicy::Wrap);
         let _ir_prog = convert_program(&mut ctx, &ast);
         let func_calls = ctx.function_calls;
         let c_v = store.by_index(5).unwrap();
@@ -328,7 +433,8 @@ f=mul(d, e)";
                 args: vec![
                     store.by_index(3).unwrap(),
                     c_v
-                ]
+                ],
+                span: Span::default()
             },
             Assign {
                 var_name: store.by_index(0).unwrap(),
@@ -336,15 +442,17 @@ f=mul(d, e)";
                 args: vec![
                     store.by_index(6).unwrap(),
                     store.by_index(7).unwrap()
-                ]
+                ],
+                span: Span::default()
             }
         ];
-        assert_eq!(func_calls, expected);
-        assert!(convert_program_to_ir(&ast, &store).is_ok());
+        assert_eq!(func_calls.iter().map(|a| a.without_spans()).collect::<Vec<_>>(), expected);
+        assert!(convert_program_to_ir(&ast, &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).is_ok());
     }
 
     #[test]
     fn test_program_conversion() {
+        let span = Span::default();
         let mut name_store = NameStore::new();
         let a_var = name_store.register("a");
         let b_var = name_store.register("b");
@@ -352,6 +460,8 @@ f=mul(d, e)";
         let incr_2_var = name_store.register("incr_2");
         let ret_var = name_store.register("ret");
         let program = Program {
+            imports: Vec::new(),
+            imported: Vec::new(),
             inputs: vec![a_var],
             output: ret_var,
             body: vec![
@@ -359,24 +469,26 @@ f=mul(d, e)";
                     name: incr_2_var,
                     parameters: vec![a_var],
                     body: vec![
-                        Incr(a_var),
-                        Incr(a_var),
-                        Incr(b_var),
-                        Return(a_var)
+                        Incr(a_var, span),
+                        Incr(a_var, span),
+                        Incr(b_var, span),
+                        Return(a_var, span)
                     ],
+                    span,
                 },
-                Incr(b_var),
-                Incr(b_var),
-                Incr(ret_var),
+                Incr(b_var, span),
+                Incr(b_var, span),
+                Incr(ret_var, span),
                 Assign {
                     var_name: c_var,
                     fun_name: incr_2_var,
                     args: vec![b_var],
+                    span,
                 }
             ],
         };
 
-        let converted = convert_program_to_ir(&program, &name_store);
+        let converted = convert_program_to_ir(&program, &name_store, DecrementPolicy::Wrap, OverflowPolicy::Wrap);
         assert!(converted.is_ok());
         let a_val = Value::new(0, a_var);
         let ret_val = Value::new(1, ret_var);
@@ -385,18 +497,19 @@ f=mul(d, e)";
         let b_val = Value::new(4, b_var);
         let c_val = Value::new(5, c_var);
 
-        let mut expected_functions = HashMap::new();
-        expected_functions.insert(incr_2_var, IRFunction {
+        let expected_functions = vec![(incr_2_var, IRFunction {
             params: vec![a_val2],
             body: IRBlock {
                 values: vec![b_val2],
                 body: vec![
-                    ValueModify(a_val2, 2),
-                    ValueModify(b_val2, 1),
-                    IRStatement::Return(a_val2)
+                    ValueModify(a_val2, 2, span),
+                    ValueModify(b_val2, 1, span),
+                    IRStatement::Return(a_val2, span)
                 ],
             },
-        });
+            span,
+            imported: false,
+        })];
 
         let expected = IRProgram {
             inputs: vec![a_val],
@@ -404,13 +517,14 @@ f=mul(d, e)";
             main: IRBlock {
                 values: vec![b_val, c_val],
                 body: vec![
-                    ValueModify(b_val, 2),
+                    ValueModify(b_val, 2, span),
                     FunctionCall {
                         func: incr_2_var,
                         target: c_val,
                         args: vec![b_val],
+                        span,
                     },
-                    ValueModify(ret_val, 1)
+                    ValueModify(ret_val, 1, span)
                 ],
             },
             functions: expected_functions,
@@ -421,6 +535,7 @@ f=mul(d, e)";
 
     #[test]
     fn test_io_conversion() {
+        let span = Span::default();
         let mut name_store = NameStore::new();
         let a_var = name_store.register("a");
         let b_var = name_store.register("b");
@@ -428,6 +543,8 @@ f=mul(d, e)";
         let d_var = name_store.register("d");
         let ret_var = name_store.register("ret");
         let program = Program {
+            imports: Vec::new(),
+            imported: Vec::new(),
             inputs: vec![
                 a_var,
                 b_var,
@@ -435,18 +552,19 @@ f=mul(d, e)";
             ],
             output: ret_var,
             body: vec![
-                Incr(d_var),
+                Incr(d_var, span),
                 While {
                     cond_var: a_var,
                     body: vec![
-                        Decr(a_var),
-                        Incr(ret_var)
+                        Decr(a_var, span),
+                        Incr(ret_var, span)
                     ],
+                    span,
                 }
             ],
         };
 
-        let converted = convert_program_to_ir(&program, &name_store);
+        let converted = convert_program_to_ir(&program, &name_store, DecrementPolicy::Wrap, OverflowPolicy::Wrap);
         assert!(converted.is_ok());
         let a_val = Value::new(0, a_var);
         let b_val = Value::new(1, b_var);
@@ -456,21 +574,110 @@ f=mul(d, e)";
         let expected = IRProgram {
             inputs: vec![a_val, b_val, c_val],
             output: ret_val,
-            functions: HashMap::new(),
+            functions: Vec::new(),
             main: IRBlock {
                 values: vec![d_val],
                 body: vec![
-                    ValueModify(d_val, 1),
+                    ValueModify(d_val, 1, span),
                     Loop {
                         condition_var: a_val,
                         body: vec![
-                            ValueModify(a_val, -1),
-                            ValueModify(ret_val, 1)
+                            ValueModify(a_val, -1, span),
+                            ValueModify(ret_val, 1, span)
                         ],
+                        span,
                     }
                 ],
             },
         };
         assert_eq!(converted.unwrap(), expected);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_saturating_decrement_merging() {
+        let span = Span::default();
+        let mut name_store = NameStore::new();
+        let a_var = name_store.register("a");
+        let b_var = name_store.register("b");
+        let ret_var = name_store.register("ret");
+        let program = Program {
+            imports: Vec::new(),
+            imported: Vec::new(),
+            inputs: vec![a_var],
+            output: ret_var,
+            body: vec![
+                Decr(a_var, span),
+                Incr(a_var, span),
+                Incr(b_var, span),
+                Incr(b_var, span),
+                Decr(b_var, span),
+                Decr(b_var, span),
+                Decr(b_var, span),
+                Incr(b_var, span)
+            ],
+        };
+
+        let a_val = Value::new(0, a_var);
+        let b_val = Value::new(2, b_var);
+
+        let wrapping = convert_program_to_ir(&program, &name_store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        assert_eq!(wrapping.main.body, vec![]);
+
+        //Only changes in the same direction are merged as soon as one of the policies does not wrap
+        let separate = vec![
+            ValueModify(a_val, -1, span),
+            ValueModify(a_val, 1, span),
+            ValueModify(b_val, 2, span),
+            ValueModify(b_val, -3, span),
+            ValueModify(b_val, 1, span)
+        ];
+        let saturating = convert_program_to_ir(&program, &name_store, DecrementPolicy::Saturate, OverflowPolicy::Wrap).unwrap();
+        assert_eq!(saturating.main.body, separate);
+        for &overflow in &[OverflowPolicy::Saturate, OverflowPolicy::Trap] {
+            let checked = convert_program_to_ir(&program, &name_store, DecrementPolicy::Wrap, overflow).unwrap();
+            assert_eq!(checked.main.body, separate, "{:?}", overflow);
+        }
+    }
+
+    #[test]
+    fn test_optimize_unreachable() {
+        let code =
+            "input: x
+output: y
+def f(a):
+    while a!=0:
+        return a
+        a-=1
+    return a
+    a+=1
+y=f(x)";
+        let (store, ast_res) = parse_program(code);
+        let mut ir = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        optimize_program(&mut ir);
+        let body = &ir.functions[0].1.body.body;
+        assert_eq!(body.len(), 2);
+        match &body[0] {
+            Loop { body, .. } => assert!(matches!(body.as_slice(), [IRStatement::Return(..)])),
+            st => panic!("Expected a loop, got {:?}", st)
+        }
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let code =
+            "input: x
+output: y
+def f(a):
+    a+=1
+y=f(x, x)
+z=g(x)";
+        let (store, ast_res) = parse_program(code);
+        let errors = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap_err();
+        let messages: Vec<String> = errors.iter().map(|e| format!("{}", e)).collect();
+        assert_eq!(messages, vec![
+            "3:1: error[E0006]: Function f has no return value",
+            "5:1: error[E0005]: Error assigning to variable y: Function f requires 1 arguments, but got 2",
+            "6:1: error[E0004]: Error assigning to variable z: Function g does not exist"
+        ]);
+    }
+}
@@ -3,6 +3,7 @@ use crate::lexer::Token::*;
 use std::iter::Peekable;
 use crate::lexer::LexerErrorKind::Unrecognized;
 use std::fmt::{Display, Formatter};
+use crate::diagnostic::{Diagnostic, INVALID_TOKEN};
 
 pub type Spanned<Tok, Loc, Error> = Result<(Loc, Tok, Loc), Error>;
 
@@ -24,6 +25,10 @@ pub enum Token<'a> {
     PlusEqualOne,
     MinusEqualOne,
     Equal,
+    Import,
+    From,
+    //Never produced by the lexer itself. The parser turns lexer errors into these, so it can recover from them.
+    Error(LexerError),
 }
 
 impl<'input> Display for Token<'input> {
@@ -33,13 +38,15 @@ impl<'input> Display for Token<'input> {
 }
 
 impl<'a> Token<'a> {
-    fn from_lexeme(lexeme: &'a str) -> Token {
+    fn from_lexeme(lexeme: &'a str) -> Token<'a> {
         match lexeme {
             "while" => While,
             "input" => Input,
             "output" => Output,
             "def" => Def,
             "return" => Return,
+            "import" => Import,
+            "from" => From,
             _ => Name(lexeme)
         }
     }
@@ -47,9 +54,9 @@ impl<'a> Token<'a> {
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
 pub struct Location {
-    line: usize,
-    col: usize,
-    pos: usize,
+    pub line: usize,
+    pub col: usize,
+    pub pos: usize,
 }
 
 impl Display for Location {
@@ -58,6 +65,34 @@ impl Display for Location {
     }
 }
 
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: Location,
+    pub end: Location,
+}
+
+impl Span {
+    pub fn new(start: Location, end: Location) -> Self {
+        Span {
+            start,
+            end
+        }
+    }
+
+    //Smallest span containing both spans
+    pub fn merge(&self, other: Span) -> Span {
+        let start = if other.start.pos < self.start.pos { other.start } else { self.start };
+        let end = if other.end.pos > self.end.pos { other.end } else { self.end };
+        Span::new(start, end)
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.start.line, self.start.col)
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
 pub enum LexerErrorKind {
     TabIndent,
@@ -86,10 +121,44 @@ impl LexerError {
             position,
         }
     }
+
+    //The character the error was found at
+    pub fn span(&self) -> Span {
+        let end = Location {
+            line: self.position.line,
+            col: self.position.col + 1,
+            pos: self.position.pos + 1,
+        };
+        Span::new(self.position, end)
+    }
+}
+
+impl std::convert::From<LexerError> for Diagnostic {
+    fn from(e: LexerError) -> Self {
+        let span = e.span();
+        match e.kind {
+            LexerErrorKind::TabIndent => Diagnostic::error("Tab indentation is not allowed")
+                .with_code(INVALID_TOKEN)
+                .with_primary(span, "tab character")
+                .with_note("indent blocks with four spaces"),
+            LexerErrorKind::Unrecognized => Diagnostic::error("Unrecognized token")
+                .with_code(INVALID_TOKEN)
+                .with_primary(span, "")
+        }
+    }
 }
 
 type LexerResult<'input> = Spanned<Token<'input>, Location, LexerError>;
 
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Comment {
+    //Everything after the #, without the line break
+    pub text: String,
+    pub span: Span,
+    //Whether the comment follows a token on the same line
+    pub trailing: bool,
+}
+
 pub struct Lexer<'input> {
     chars: Peekable<Chars<'input>>,
     input: &'input str,
@@ -101,6 +170,8 @@ pub struct Lexer<'input> {
     col: usize,
     indent_count: u32,
     buffer: Vec<LexerResult<'input>>,
+    //Comments are only kept if requested, the parser does not need them
+    comments: Option<Vec<Comment>>,
 }
 
 impl<'input> Lexer<'input> {
@@ -116,9 +187,20 @@ impl<'input> Lexer<'input> {
             parse_indent: true,
             indent_count: 0,
             buffer: Vec::new(),
+            comments: None,
         }
     }
 
+    pub fn with_comments(mut self) -> Self {
+        self.comments = Some(Vec::new());
+        self
+    }
+
+    //Returns the comments read so far
+    pub fn take_comments(&mut self) -> Vec<Comment> {
+        self.comments.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
     fn current_pos(&self) -> Location {
         Location {
             pos: self.pos,
@@ -141,16 +223,29 @@ impl<'input> Lexer<'input> {
         self.pos += 1;
     }
 
-    fn comment(&mut self) {
-        loop {
+    fn comment(&mut self, start: Location) {
+        let trailing = start.col as i32 != self.indent_level + 1;
+        let mut text = String::new();
+        let end = loop {
             match self.chars.next() {
-                None => break,
+                None => break self.current_pos(),
                 Some('\n') => {
+                    let end = self.current_pos();
                     self.incr_line();
-                    break;
+                    break end;
+                }
+                Some(c) => {
+                    self.incr_pos();
+                    text.push(c);
                 }
-                Some(_) => self.incr_pos()
             }
+        };
+        if let Some(comments) = &mut self.comments {
+            comments.push(Comment {
+                text: String::from(text.trim_end()),
+                span: Span::new(start, end),
+                trailing,
+            });
         }
     }
 
@@ -262,10 +357,7 @@ fn single_char_token<'a>(c: char) -> Option<Token<'a>> {
 }
 
 fn is_separator(c: char) -> bool {
-    match c {
-        ':' | ',' | ' ' | '\n' | '\t' | '\r' | '(' | ')' | '!' | '+' | '-' | '#' | '=' => true,
-        _ => false
-    }
+    matches!(c, ':' | ',' | ' ' | '\n' | '\t' | '\r' | '(' | ')' | '!' | '+' | '-' | '#' | '=')
 }
 
 impl<'input> Iterator for Lexer<'input> {
@@ -280,6 +372,8 @@ impl<'input> Iterator for Lexer<'input> {
                     match self.chars.peek() {
                         None => (),
                         Some(' ') => (),
+                        //Comments on their own line do not open or close blocks
+                        Some('#') => (),
                         Some(_) => {
                             self.handle_indent();
                         }
@@ -319,8 +413,15 @@ impl<'input> Iterator for Lexer<'input> {
                             }
                             '\r' => self.incr_pos(),
                             '#' => {
+                                let start = self.current_pos();
                                 self.incr_pos();
-                                self.comment()
+                                //The indentation of the line before an own-line comment still applies after it
+                                let own_line = self.parse_indent;
+                                let last_indent_level = self.last_indent_level;
+                                self.comment(start);
+                                if own_line {
+                                    self.last_indent_level = last_indent_level;
+                                }
                             }
                             '!' => {
                                 break self.not_eq_zero();
@@ -352,7 +453,7 @@ impl<'input> Iterator for Lexer<'input> {
 
                                         let lexeme = &self.input[pos.pos..=curr];
                                         let token = Token::from_lexeme(lexeme);
-                                        break (Some(Ok((pos, token, self.current_pos()))));
+                                        break Some(Ok((pos, token, self.current_pos())));
                                     }
                                 }
                             }
@@ -450,6 +551,30 @@ d";
         lex_equal(code, tokens);
     }
 
+    #[test]
+    fn test_lexer_comment_indentation() {
+        let code =
+            "def f(a):
+    while a!=0:
+#start of line
+        a-=1
+      #between levels
+        a-=1
+    return a";
+        let tokens = vec![Def, Name("f"), OpenParen, Name("a"), CloseParen, Colon, Indent, While, Name("a"), NotEqualZero, Colon, Indent,
+                          Name("a"), MinusEqualOne, Name("a"), MinusEqualOne, Unindent, Return, Name("a"), Unindent];
+        lex_equal(code, tokens);
+    }
+
+    #[test]
+    fn test_lexer_imports() {
+        let code =
+            "import stdlib
+from math import add, mul";
+        let tokens = vec![Import, Name("stdlib"), From, Name("math"), Import, Name("add"), Comma, Name("mul")];
+        lex_equal(code, tokens);
+    }
+
     #[test]
     fn test_lexer_9() {
         let code =
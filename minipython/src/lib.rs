@@ -1,10 +1,24 @@
 #[macro_use] extern crate lalrpop_util;
 
-mod name;
-mod ast;
+pub mod name;
+pub mod ast;
 mod lexer;
+mod diagnostic;
 mod parser;
-mod value;
-mod ir;
-mod codegen;
-pub mod compiler;
\ No newline at end of file
+mod resolver;
+pub mod value;
+pub mod ir;
+mod callgraph;
+mod warnings;
+pub mod codegen;
+mod llvm;
+mod wasm;
+mod rust;
+mod asm;
+mod bytecode;
+mod vm;
+mod interpreter;
+mod format;
+pub mod compiler;
+pub mod build;
+pub mod analysis;
\ No newline at end of file
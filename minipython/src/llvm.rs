@@ -0,0 +1,265 @@
+use crate::ir::*;
+use crate::name::*;
+use std::error::Error;
+use std::io::Write;
+use crate::value::Value;
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+
+//Pointers are opaque (ptr), which needs LLVM 15 or newer, typed pointers are gone since LLVM 17
+const LLVM_VALUE_TYPE: &str = "i64";
+const LLVM_INPUT_FORMAT: &str = "%llu";
+
+//Every value lives in a stack slot, LLVM's mem2reg pass turns them into registers
+fn to_value_slot(v: Value, name_store: &NameStore) -> String {
+    format!("%{}_{}", v.get_name(name_store).unwrap(), v.get_id())
+}
+
+//Functions get a prefix, so they cannot clash with main or the declared C functions
+fn to_function_name(name: InternedName, name_store: &NameStore) -> String {
+    format!("@mp_{}", name_store.get(name).unwrap())
+}
+
+fn to_param_name(v: Value, name_store: &NameStore) -> String {
+    format!("%{}_{}.param", v.get_name(name_store).unwrap(), v.get_id())
+}
+
+fn to_string_constant(s: &str) -> String {
+    format!("[{} x i8] c\"{}\\00\"", s.len() + 1, s)
+}
+
+struct FunctionState {
+    next_temp: u64,
+    next_label: u64,
+}
+
+impl FunctionState {
+    fn new() -> Self {
+        FunctionState {
+            next_temp: 0,
+            next_label: 0
+        }
+    }
+
+    fn temp(&mut self) -> String {
+        let id = self.next_temp;
+        self.next_temp += 1;
+        format!("%t{}", id)
+    }
+
+    fn label(&mut self) -> u64 {
+        let id = self.next_label;
+        self.next_label += 1;
+        id
+    }
+}
+
+fn write_value_init(output: &mut dyn Write, slot: &str) -> Result<(), Box<dyn Error>> {
+    writeln!(output, "  {} = alloca {}", slot, LLVM_VALUE_TYPE)?;
+    writeln!(output, "  store {} 0, ptr {}", LLVM_VALUE_TYPE, slot)?;
+    Ok(())
+}
+
+fn write_load(output: &mut dyn Write, state: &mut FunctionState, slot: &str) -> Result<String, Box<dyn Error>> {
+    let temp = state.temp();
+    writeln!(output, "  {} = load {}, ptr {}", temp, LLVM_VALUE_TYPE, slot)?;
+    Ok(temp)
+}
+
+fn compile_statement(statement: &IRStatement, name_store: &NameStore, state: &mut FunctionState, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    match statement {
+        ValueModify(val, change, _) => {
+            let slot = to_value_slot(*val, name_store);
+            let old = write_load(output, state, &slot)?;
+            let new = state.temp();
+            writeln!(output, "  {} = add {} {}, {}", new, LLVM_VALUE_TYPE, old, change)?;
+            writeln!(output, "  store {} {}, ptr {}", LLVM_VALUE_TYPE, new, slot)?;
+        },
+        Return(val, _) => {
+            let ret = write_load(output, state, &to_value_slot(*val, name_store))?;
+            writeln!(output, "  ret {} {}", LLVM_VALUE_TYPE, ret)?;
+            //Anything after a return is unreachable, but still needs a block to live in
+            writeln!(output, "dead.{}:", state.label())?;
+        },
+        Loop { condition_var, body, .. } => {
+            let id = state.label();
+            writeln!(output, "  br label %loop.cond.{}", id)?;
+            writeln!(output, "loop.cond.{}:", id)?;
+            let cond = write_load(output, state, &to_value_slot(*condition_var, name_store))?;
+            let cmp = state.temp();
+            writeln!(output, "  {} = icmp ne {} {}, 0", cmp, LLVM_VALUE_TYPE, cond)?;
+            writeln!(output, "  br i1 {}, label %loop.body.{}, label %loop.end.{}", cmp, id, id)?;
+            writeln!(output, "loop.body.{}:", id)?;
+
+            for st in body {
+                compile_statement(st, name_store, state, output)?;
+            }
+
+            writeln!(output, "  br label %loop.cond.{}", id)?;
+            writeln!(output, "loop.end.{}:", id)?;
+        },
+        FunctionCall { func, args, target, .. } => {
+            let func_name = to_function_name(*func, name_store);
+            let mut arg_temps = Vec::new();
+            for &arg in args {
+                arg_temps.push(write_load(output, state, &to_value_slot(arg, name_store))?);
+            }
+            let args_list = arg_temps.iter().map(|t| format!("{} {}", LLVM_VALUE_TYPE, t)).collect::<Vec<String>>().join(", ");
+            let result = state.temp();
+            writeln!(output, "  {} = call {} {}({})", result, LLVM_VALUE_TYPE, func_name, args_list)?;
+            writeln!(output, "  store {} {}, ptr {}", LLVM_VALUE_TYPE, result, to_value_slot(*target, name_store))?;
+        }
+    }
+    Ok(())
+}
+
+fn compile_block(block: &IRBlock, name_store: &NameStore, state: &mut FunctionState, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    for &val in &block.values {
+        write_value_init(output, &to_value_slot(val, name_store))?;
+    }
+
+    for statement in &block.body {
+        compile_statement(statement, name_store, state, output)?;
+    }
+
+    Ok(())
+}
+
+pub fn compile_to_llvm(program: &IRProgram, name_store: &NameStore, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    //Format strings for the I/O in main
+    for &input_val in &program.inputs {
+        let prompt = format!("{}=", input_val.get_name(name_store).unwrap());
+        writeln!(output, "@.prompt.{} = private unnamed_addr constant {}", to_value_slot(input_val, name_store).trim_start_matches('%'), to_string_constant(&prompt))?;
+    }
+    writeln!(output, "@.input_format = private unnamed_addr constant {}", to_string_constant(LLVM_INPUT_FORMAT))?;
+    let output_format = format!("{}={}", program.output.get_name(name_store).unwrap(), LLVM_INPUT_FORMAT);
+    writeln!(output, "@.output_format = private unnamed_addr constant {}", to_string_constant(&output_format))?;
+    writeln!(output, "declare i32 @printf(ptr, ...)")?;
+    writeln!(output, "declare i32 @scanf(ptr, ...)")?;
+
+    for (function_name, function) in &program.functions {
+        let params = function.params.iter().map(|&v| format!("{} {}", LLVM_VALUE_TYPE, to_param_name(v, name_store))).collect::<Vec<String>>().join(", ");
+        writeln!(output, "define {} {}({}) {{", LLVM_VALUE_TYPE, to_function_name(*function_name, name_store), params)?;
+        writeln!(output, "entry:")?;
+
+        for &param in &function.params {
+            let slot = to_value_slot(param, name_store);
+            writeln!(output, "  {} = alloca {}", slot, LLVM_VALUE_TYPE)?;
+            writeln!(output, "  store {} {}, ptr {}", LLVM_VALUE_TYPE, to_param_name(param, name_store), slot)?;
+        }
+
+        let mut state = FunctionState::new();
+        compile_block(&function.body, name_store, &mut state, output)?;
+
+        //The last block can only be reached if the function does not return
+        writeln!(output, "  unreachable")?;
+        writeln!(output, "}}")?;
+    }
+
+    writeln!(output, "define i32 @main(i32 %argc, ptr %argv) {{")?;
+    writeln!(output, "entry:")?;
+
+    let mut state = FunctionState::new();
+
+    for &input_val in &program.inputs {
+        let slot = to_value_slot(input_val, name_store);
+        write_value_init(output, &slot)?;
+        writeln!(output, "  call i32 (ptr, ...) @printf(ptr @.prompt.{})", slot.trim_start_matches('%'))?;
+        writeln!(output, "  call i32 (ptr, ...) @scanf(ptr @.input_format, ptr {})", slot)?;
+    }
+
+    let output_slot = to_value_slot(program.output, name_store);
+    write_value_init(output, &output_slot)?;
+
+    compile_block(&program.main, name_store, &mut state, output)?;
+
+    let result = write_load(output, &mut state, &output_slot)?;
+    writeln!(output, "  call i32 (ptr, ...) @printf(ptr @.output_format, {} {})", LLVM_VALUE_TYPE, result)?;
+    writeln!(output, "  ret i32 0")?;
+    writeln!(output, "}}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::CodegenOptions;
+    use crate::compiler::{compile_to_string, CheckOptions, Target};
+
+    #[test]
+    fn test_llvm_golden() {
+        let code =
+            "input: x
+output: y
+def f(a):
+    while a!=0:
+        a-=1
+        return a
+    #endwhile
+    return a
+#enddef
+y=f(x)
+y+=1
+";
+        let expected =
+            r#"@.prompt.x_0 = private unnamed_addr constant [3 x i8] c"x=\00"
+@.input_format = private unnamed_addr constant [5 x i8] c"%llu\00"
+@.output_format = private unnamed_addr constant [7 x i8] c"y=%llu\00"
+declare i32 @printf(ptr, ...)
+declare i32 @scanf(ptr, ...)
+define i64 @mp_f(i64 %a_2.param) {
+entry:
+  %a_2 = alloca i64
+  store i64 %a_2.param, ptr %a_2
+  br label %loop.cond.0
+loop.cond.0:
+  %t0 = load i64, ptr %a_2
+  %t1 = icmp ne i64 %t0, 0
+  br i1 %t1, label %loop.body.0, label %loop.end.0
+loop.body.0:
+  %t2 = load i64, ptr %a_2
+  %t3 = add i64 %t2, -1
+  store i64 %t3, ptr %a_2
+  %t4 = load i64, ptr %a_2
+  ret i64 %t4
+dead.1:
+  br label %loop.cond.0
+loop.end.0:
+  %t5 = load i64, ptr %a_2
+  ret i64 %t5
+dead.2:
+  unreachable
+}
+define i32 @main(i32 %argc, ptr %argv) {
+entry:
+  %x_0 = alloca i64
+  store i64 0, ptr %x_0
+  call i32 (ptr, ...) @printf(ptr @.prompt.x_0)
+  call i32 (ptr, ...) @scanf(ptr @.input_format, ptr %x_0)
+  %y_1 = alloca i64
+  store i64 0, ptr %y_1
+  %t0 = load i64, ptr %x_0
+  %t1 = call i64 @mp_f(i64 %t0)
+  store i64 %t1, ptr %y_1
+  %t2 = load i64, ptr %y_1
+  %t3 = add i64 %t2, 1
+  store i64 %t3, ptr %y_1
+  %t4 = load i64, ptr %y_1
+  call i32 (ptr, ...) @printf(ptr @.output_format, i64 %t4)
+  ret i32 0
+}
+"#;
+        let (ir, _) = compile_to_string(code, Target::Llvm, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        assert_eq!(ir, expected);
+    }
+
+    #[test]
+    fn test_llvm_function_names() {
+        let code = "input: x\noutput: y\ndef main(a):\n    return a\n#enddef\ndef printf(a):\n    a=main(a)\n    return a\n#enddef\ny=printf(x)\n";
+        let (ir, _) = compile_to_string(code, Target::Llvm, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        assert!(ir.contains("define i64 @mp_main(i64 %a_2.param) {"));
+        assert!(ir.contains("call i64 @mp_main("));
+        assert!(ir.contains("call i64 @mp_printf("));
+        assert_eq!(ir.matches("define i32 @main(").count(), 1);
+        assert!(!ir.contains("define i64 @printf("));
+    }
+}
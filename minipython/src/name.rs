@@ -15,6 +15,12 @@ pub struct NameStore {
     next_id: usize
 }
 
+impl Default for NameStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl NameStore {
     pub fn new() -> Self {
         NameStore {
@@ -24,7 +30,7 @@ impl NameStore {
     }
 
     pub fn get_by_interned(&self, name: &str) -> Option<InternedName> {
-        self.store.iter().position(|v| v == &name).map(|id| InternedName(id))
+        self.store.iter().position(|v| v == name).map(InternedName)
     }
 
     pub fn get(&self, n: InternedName) -> Option<&String> {
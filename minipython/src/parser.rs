@@ -1,14 +1,136 @@
 use crate::ast::*;
 use crate::name::*;
-use crate::lexer::Lexer;
+use crate::lexer::{Lexer, Location, Token, LexerError, Span};
+use crate::diagnostic::{Diagnostic, UNEXPECTED_TOKEN, UNEXPECTED_EOF, INVALID_TOKEN};
+use lalrpop_util::{ErrorRecovery, ParseError};
 
-lalrpop_mod!(pub minipython);
+lalrpop_mod!(#[allow(clippy::all, unused)] pub minipython);
 
-pub fn parse_program(code: &str) -> (NameStore, Result<Program, String>) {
+fn describe_token(token: &Token) -> String {
+    match token {
+        Token::Name(name) => format!("`{}`", name),
+        Token::Indent => String::from("indentation"),
+        Token::Unindent => String::from("end of block"),
+        Token::Input => String::from("`input`"),
+        Token::Output => String::from("`output`"),
+        Token::Comma => String::from("`,`"),
+        Token::Return => String::from("`return`"),
+        Token::Def => String::from("`def`"),
+        Token::Colon => String::from("`:`"),
+        Token::OpenParen => String::from("`(`"),
+        Token::CloseParen => String::from("`)`"),
+        Token::While => String::from("`while`"),
+        Token::NotEqualZero => String::from("`!=0`"),
+        Token::PlusEqualOne => String::from("`+=1`"),
+        Token::MinusEqualOne => String::from("`-=1`"),
+        Token::Equal => String::from("`=`"),
+        Token::Import => String::from("`import`"),
+        Token::From => String::from("`from`"),
+        Token::Error(_) => String::from("invalid token")
+    }
+}
+
+fn expected_note(expected: &[String]) -> String {
+    let names: Vec<String> = expected.iter().map(|e| format!("`{}`", e.trim_matches('"'))).collect();
+    format!("expected one of {}", names.join(", "))
+}
+
+fn parse_error_to_diagnostic(error: ParseError<Location, Token, LexerError>) -> Diagnostic {
+    match error {
+        ParseError::InvalidToken { location } => Diagnostic::error("Invalid token")
+            .with_code(INVALID_TOKEN)
+            .with_primary(Span::new(location, location), ""),
+        ParseError::UnrecognizedEOF { location, expected } => Diagnostic::error("Unexpected end of file")
+            .with_code(UNEXPECTED_EOF)
+            .with_primary(Span::new(location, location), "")
+            .with_note(expected_note(&expected)),
+        ParseError::UnrecognizedToken { token: (_, Token::Error(error), _), .. } => error.into(),
+        ParseError::UnrecognizedToken { token: (l, token, r), expected } => Diagnostic::error(format!("Unexpected {}", describe_token(&token)))
+            .with_code(UNEXPECTED_TOKEN)
+            .with_primary(Span::new(l, r), "")
+            .with_note(expected_note(&expected)),
+        ParseError::ExtraToken { token: (l, token, r) } => Diagnostic::error(format!("Unexpected {}", describe_token(&token)))
+            .with_code(UNEXPECTED_TOKEN)
+            .with_primary(Span::new(l, r), ""),
+        ParseError::User { error } => error.into()
+    }
+}
+
+type RecoveredErrors<'input> = Vec<ErrorRecovery<Location, Token<'input>, LexerError>>;
+
+//Lexer errors become error tokens, which the error recovery of the grammar skips like any other unexpected token
+fn lex_with_error_tokens(code: &str) -> impl Iterator<Item = Result<(Location, Token<'_>, Location), LexerError>> {
+    Lexer::new(code).map(|token| token.or_else(|error| {
+        let span = error.span();
+        Ok((span.start, Token::Error(error), span.end))
+    }))
+}
+
+fn collect_diagnostics<T>(res: Result<T, ParseError<Location, Token, LexerError>>, errors: RecoveredErrors) -> (Option<T>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    for recovery in errors {
+        diagnostics.push(parse_error_to_diagnostic(recovery.error));
+        //Invalid tokens skipped while recovering from an earlier error are reported too
+        for (_, token, _) in recovery.dropped_tokens {
+            if let Token::Error(error) = token {
+                diagnostics.push(error.into());
+            }
+        }
+    }
+    let parsed = match res {
+        Ok(parsed) => Some(parsed),
+        Err(e) => {
+            diagnostics.push(parse_error_to_diagnostic(e));
+            None
+        }
+    };
+    diagnostics.sort_by_key(|d| d.primary.as_ref().map(|l| (l.span.start.pos, l.span.end.pos)));
+    //An invalid token is reported both as the error and as a dropped token when recovery starts at it
+    diagnostics.dedup_by(|a, b| a.primary == b.primary && a.code == b.code && a.message == b.message);
+    (parsed, diagnostics)
+}
+
+//Parses as much of the program as possible. The program is only missing if the parser could not recover from an error.
+pub fn parse_program_with_recovery(code: &str) -> (NameStore, Option<Program>, Vec<Diagnostic>) {
     let mut name_store = NameStore::new();
+    let mut errors = Vec::new();
     let parser = minipython::ProgramParser::new();
-    let lexer = Lexer::new(code);
-    let res = parser.parse(code, &mut name_store, lexer).map_err(|e| format!("{}", e));
+    let res = parser.parse(code, &mut name_store, &mut errors, lex_with_error_tokens(code));
+    let (program, diagnostics) = collect_diagnostics(res, errors);
+    (name_store, program, diagnostics)
+}
+
+pub fn parse_program(code: &str) -> (NameStore, Result<Program, Vec<Diagnostic>>) {
+    let (name_store, program, diagnostics) = parse_program_with_recovery(code);
+    let res = match program {
+        Some(program) if diagnostics.is_empty() => Ok(program),
+        _ => Err(diagnostics)
+    };
+    (name_store, res)
+}
+
+//Modules are files without input and output, which only define functions.
+//Only looks for the input keyword, so files with syntax errors are still told apart.
+pub fn is_module(code: &str) -> bool {
+    !Lexer::new(code).any(|token| matches!(token, Ok((_, Token::Input, _))))
+}
+
+//Like parse_program_with_recovery, but for a module, which may only contain imports and function definitions
+pub fn parse_module_with_recovery(code: &str) -> (NameStore, Option<Module>, Vec<Diagnostic>) {
+    let mut name_store = NameStore::new();
+    let mut errors = Vec::new();
+    let parser = minipython::ModuleParser::new();
+    let res = parser.parse(code, &mut name_store, &mut errors, lex_with_error_tokens(code));
+    let (module, diagnostics) = collect_diagnostics(res, errors);
+    (name_store, module, diagnostics)
+}
+
+pub fn parse_module(code: &str) -> (NameStore, Result<Module, Vec<Diagnostic>>) {
+    let (name_store, module, diagnostics) = parse_module_with_recovery(code);
+    let res = match module {
+        Some(module) if diagnostics.is_empty() => Ok(module),
+        _ => Err(diagnostics)
+    };
     (name_store, res)
 }
 
@@ -16,24 +138,34 @@ pub fn parse_program(code: &str) -> (NameStore, Result<Program, String>) {
 #[allow(dead_code)]
 fn parse_block(code: &str) -> (NameStore, Result<Vec<Ast>, String>) {
     let mut name_store = NameStore::new();
+    let mut errors = Vec::new();
     let parser = minipython::TopLevelBlockParser::new();
     let lexer = Lexer::new(code);
-    let res = parser.parse(code, &mut name_store, lexer).map_err(|e| format!("{}", e));
-    (name_store, res)
+    let res = parser.parse(code, &mut name_store, &mut errors, lexer).map_err(|e| format!("{}", e));
+    match errors.first() {
+        Some(e) => (name_store, Err(format!("{}", e.error))),
+        None => (name_store, res)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::ast::Ast::*;
+    use crate::lexer::Span;
+
+    fn without_spans(body: Vec<Ast>) -> Vec<Ast> {
+        body.iter().map(Ast::without_spans).collect()
+    }
 
     #[test]
     fn test_incr_decr() {
         let code = "a+=1";
+        let span = Span::default();
         let (store, res) = parse_block(code);
         assert!(res.is_ok(), "{:#?}", res);
-        let expected = vec![Incr (store.by_index(0).unwrap())];
-        assert_eq!(res.unwrap(), expected);
+        let expected = vec![Incr(store.by_index(0).unwrap(), span)];
+        assert_eq!(without_spans(res.unwrap()), expected);
     }
 
     #[test]
@@ -42,15 +174,17 @@ mod tests {
             "while x != 0:
     x-=1
 ";
+        let span = Span::default();
         let (store, res) = parse_block(code);
         assert!(res.is_ok(), "{:#?}", res);
         let expected = vec![While {
             cond_var: store.by_index(0).unwrap(),
             body: vec![
-                Decr(store.by_index(0).unwrap())
+                Decr(store.by_index(0).unwrap(), span)
             ],
+            span
         }];
-        assert_eq!(res.unwrap(), expected);
+        assert_eq!(without_spans(res.unwrap()), expected);
     }
 
     #[test]
@@ -64,6 +198,7 @@ mod tests {
 x+=1
 y+=1
 z=add(x, y)";
+        let span = Span::default();
         let (store, res) = parse_block(code);
         assert!(res.is_ok(), "{:#?}", res);
         let add_var = store.get_by_interned("add").unwrap();
@@ -79,21 +214,24 @@ z=add(x, y)";
                 While {
                     cond_var: a_var,
                     body: vec![
-                        Decr(a_var),
-                        Incr(b_var)
+                        Decr(a_var, span),
+                        Incr(b_var, span)
                     ],
+                    span
                 },
-                Return(b_var)
+                Return(b_var, span)
             ],
+            span
         },
-                            Incr(x_var),
-                            Incr(y_var),
+                            Incr(x_var, span),
+                            Incr(y_var, span),
                             Assign {
                                 var_name: z_var,
                                 fun_name: add_var,
                                 args: vec![x_var, y_var],
+                                span
                             }];
-        assert_eq!(res.unwrap(), expected);
+        assert_eq!(without_spans(res.unwrap()), expected);
     }
 
     #[test]
@@ -105,18 +243,95 @@ while x!=0:
     x-=1
     y+=1
 ";
+        let span = Span::default();
         let (store, res) = parse_program(code);
         assert!(res.is_ok(), "{:#?}", res);
         let x_var = store.get_by_interned("x").unwrap();
         let y_var = store.get_by_interned("y").unwrap();
         let expected = Program {
+            imports: Vec::new(),
+            imported: Vec::new(),
             inputs: vec![x_var, y_var],
             output: y_var,
             body: vec! [ While {
                 cond_var: x_var,
-                body: vec! [Decr(x_var), Incr(y_var)]
+                body: vec! [Decr(x_var, span), Incr(y_var, span)],
+                span
             }]
         };
-        assert_eq!(res.unwrap(), expected);
+        let mut program = res.unwrap();
+        program.body = without_spans(program.body);
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn test_spans() {
+        let code =
+            "def f(a):
+    a+=1
+    return a
+x=f(x)";
+        let (_, res) = parse_block(code);
+        let body = res.unwrap();
+        let spans: Vec<String> = match &body[0] {
+            Def { body, span, .. } => std::iter::once(span).chain(body.iter().map(|st| match st {
+                Incr(_, span) | Return(_, span) => span,
+                _ => unreachable!()
+            })).map(|s| format!("{}-{}:{}", s, s.end.line, s.end.col)).collect(),
+            _ => unreachable!()
+        };
+        assert_eq!(spans, vec!["1:1-3:13", "2:5-2:9", "3:5-3:13"]);
+        match &body[1] {
+            Assign { span, .. } => assert_eq!(format!("{}-{}:{}", span, span.end.line, span.end.col), "4:1-4:7"),
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_error_recovery() {
+        let code =
+            "input: x
+output: y
+def f(a:
+    a+=1
+    return a
+y=(x)
+while x:
+    x-=1
+y+=1
+";
+        let (store, program, diagnostics) = parse_program_with_recovery(code);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.primary.as_ref().unwrap().span.start.line).collect();
+        //Both parentheses of y=(x) are unexpected
+        assert_eq!(lines, vec![3, 6, 6, 7]);
+        let y_var = store.get_by_interned("y").unwrap();
+        assert_eq!(without_spans(program.unwrap().body), vec![Incr(y_var, Span::default())]);
+
+        let (_, res) = parse_program(code);
+        assert_eq!(res.unwrap_err().len(), 4);
+    }
+
+    #[test]
+    fn test_lexer_error_recovery() {
+        let code =
+            "input: x
+output: y
+x+=2
+while x!=0:
+    x-=1
+y=(x)
+y+=1
+";
+        let (store, program, diagnostics) = parse_program_with_recovery(code);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.primary.as_ref().unwrap().span.start.line).collect();
+        assert_eq!(lines, vec![3, 6, 6]);
+        assert_eq!(diagnostics[0].primary.as_ref().unwrap().span.start.col, 2);
+        let x_var = store.get_by_interned("x").unwrap();
+        let y_var = store.get_by_interned("y").unwrap();
+        let expected = vec![
+            While { cond_var: x_var, body: vec![Decr(x_var, Span::default())], span: Span::default() },
+            Incr(y_var, Span::default())
+        ];
+        assert_eq!(without_spans(program.unwrap().body), expected);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,432 @@
+use crate::ast::{Ast, Import, Module, Program};
+use crate::name::{InternedName, NameStore};
+use crate::parser;
+use crate::ir;
+use crate::codegen::OverflowPolicy;
+use crate::diagnostic::{Diagnostic, MODULE_NOT_FOUND, IMPORT_CYCLE, UNKNOWN_IMPORT, IMPORT_CONFLICT};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::fs;
+
+//Embedded in the crate, so programs can use it without search paths
+const PRELUDE: &str = include_str!("prelude.mpy");
+
+//A function that is available in a module, either defined or imported there
+#[derive(Clone)]
+struct ModuleFunction {
+    name: InternedName,
+    definition: Ast,
+    //Tells apart functions with the same name from different files
+    origin: PathBuf,
+}
+
+//The names of a module's functions are interned in the module's own NameStore
+struct LoadedModule {
+    names: NameStore,
+    //Imported functions first, then the ones defined in the module
+    functions: Vec<ModuleFunction>,
+}
+
+//Loads imported modules, looking in the directory of the importing file first and then in the search paths.
+//Every module is parsed and checked once, even if it is imported several times.
+pub struct Resolver<'a> {
+    search_paths: &'a [PathBuf],
+    modules: HashMap<PathBuf, LoadedModule>,
+    //The files that are currently being loaded, in import order
+    loading: Vec<PathBuf>,
+    use_prelude: bool,
+    //Parsed when it is first needed
+    prelude: Option<LoadedModule>,
+    //Every module file that was found, including the ones that failed to load
+    files: Vec<PathBuf>,
+}
+
+//Interns the names of a tree from one NameStore in another
+fn translate(ast: &Ast, from: &NameStore, to: &mut NameStore) -> Ast {
+    let mut name = |n: &InternedName| to.register(from.get(*n).unwrap());
+    match ast {
+        Ast::Def { name: f, parameters, body, span } => Ast::Def {
+            name: name(f),
+            parameters: parameters.iter().map(&mut name).collect(),
+            body: body.iter().map(|st| translate(st, from, to)).collect(),
+            span: *span
+        },
+        Ast::Return(n, span) => Ast::Return(name(n), *span),
+        Ast::While { cond_var, body, span } => {
+            let cond_var = name(cond_var);
+            Ast::While { cond_var, body: body.iter().map(|st| translate(st, from, to)).collect(), span: *span }
+        },
+        Ast::Assign { var_name, fun_name, args, span } => Ast::Assign {
+            var_name: name(var_name),
+            fun_name: name(fun_name),
+            args: args.iter().map(&mut name).collect(),
+            span: *span
+        },
+        Ast::Incr(n, span) => Ast::Incr(name(n), *span),
+        Ast::Decr(n, span) => Ast::Decr(name(n), *span)
+    }
+}
+
+fn collect_callees(statements: &[Ast], callees: &mut Vec<InternedName>) {
+    for statement in statements {
+        match statement {
+            Ast::Assign { fun_name, .. } => callees.push(*fun_name),
+            Ast::While { body, .. } | Ast::Def { body, .. } => collect_callees(body, callees),
+            _ => ()
+        }
+    }
+}
+
+fn load_prelude() -> LoadedModule {
+    let (names, module) = parser::parse_module(PRELUDE);
+    let functions = module.expect("The prelude has syntax errors").functions.into_iter()
+        .map(|definition| match definition {
+            Ast::Def { name, .. } => ModuleFunction { name, definition, origin: PathBuf::from("prelude") },
+            _ => unreachable!()
+        })
+        .collect();
+    LoadedModule { names, functions }
+}
+
+fn in_file(diagnostic: Diagnostic, file: Option<&Path>) -> Diagnostic {
+    match file {
+        Some(file) => diagnostic.with_file(file),
+        None => diagnostic
+    }
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(search_paths: &'a [PathBuf]) -> Self {
+        Resolver {
+            search_paths,
+            modules: HashMap::new(),
+            loading: Vec::new(),
+            use_prelude: true,
+            prelude: None,
+            files: Vec::new(),
+        }
+    }
+
+    pub fn with_prelude(mut self, prelude: bool) -> Self {
+        self.use_prelude = prelude;
+        self
+    }
+
+    //The files of the modules imported so far, directly or by other modules
+    pub fn module_files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    //Adds the imported and the used prelude functions to the body of the program. The file is used to find modules next to it,
+    //a program without a file only uses the search paths.
+    pub fn resolve_program(&mut self, program: &mut Program, names: &mut NameStore, file: Option<&Path>) -> Result<(), Vec<Diagnostic>> {
+        if !program.imports.is_empty() {
+            self.add_imports(program, names, file)?;
+        }
+        let prelude = self.add_prelude(&mut program.body, names);
+        program.imported.extend(prelude);
+        Ok(())
+    }
+
+    fn add_imports(&mut self, program: &mut Program, names: &mut NameStore, file: Option<&Path>) -> Result<(), Vec<Diagnostic>> {
+        if let Some(canonical) = file.and_then(|f| fs::canonicalize(f).ok()) {
+            self.loading.push(canonical);
+        }
+        let dir = file.and_then(Path::parent);
+        let imported = self.resolve_imports(&program.imports, names, dir, None);
+        self.loading.clear();
+        let imported = imported?;
+
+        let mut errors = Vec::new();
+        for definition in &program.body {
+            if let Ast::Def { name, span, .. } = definition {
+                if let Some(function) = imported.iter().find(|f| f.name == *name) {
+                    errors.push(Diagnostic::error(format!("Function {} is already imported from {}", names.get(*name).unwrap(), display_name(&function.origin)))
+                        .with_code(IMPORT_CONFLICT)
+                        .with_primary(*span, ""));
+                }
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        program.imports.clear();
+        program.imported = imported.iter().map(|f| f.name).collect();
+        let body = std::mem::take(&mut program.body);
+        program.body = imported.into_iter().map(|f| f.definition).chain(body).collect();
+        Ok(())
+    }
+
+    //Adds the prelude functions that are called but not defined, including the ones they call themselves.
+    //Returns the names of the added functions.
+    fn add_prelude(&mut self, body: &mut Vec<Ast>, names: &mut NameStore) -> Vec<InternedName> {
+        if !self.use_prelude {
+            return Vec::new();
+        }
+        let prelude = self.prelude.get_or_insert_with(load_prelude);
+
+        let mut defined: Vec<InternedName> = body.iter().filter_map(|definition| match definition {
+            Ast::Def { name, .. } => Some(*name),
+            _ => None
+        }).collect();
+        let mut pending = Vec::new();
+        collect_callees(body, &mut pending);
+        let mut added = Vec::new();
+        while let Some(name) = pending.pop() {
+            if defined.contains(&name) {
+                continue;
+            }
+            let index = match names.get(name).and_then(|n| prelude.names.get_by_interned(n)).and_then(|n| prelude.functions.iter().position(|f| f.name == n)) {
+                Some(index) => index,
+                //Reported as an unknown function later
+                None => continue
+            };
+            defined.push(name);
+            let definition = translate(&prelude.functions[index].definition, &prelude.names, names);
+            collect_callees(std::slice::from_ref(&definition), &mut pending);
+            added.push((index, name, definition));
+        }
+
+        //In the order of the prelude, so the output does not depend on the order of the calls
+        added.sort_by_key(|(index, _, _)| *index);
+        let added_names = added.iter().map(|(_, name, _)| *name).collect();
+        let rest = std::mem::take(body);
+        *body = added.into_iter().map(|(_, _, definition)| definition).chain(rest).collect();
+        added_names
+    }
+
+    fn find_module(&self, module: &str, dir: Option<&Path>) -> Option<PathBuf> {
+        let file_name = format!("{}.mpy", module);
+        dir.into_iter().chain(self.search_paths.iter().map(PathBuf::as_path))
+            .map(|d| d.join(&file_name))
+            .find(|path| path.is_file())
+    }
+
+    //Returns the imported functions with their names interned in names.
+    //Diagnostics are attributed to file, which is None for the compiled program itself.
+    fn resolve_imports(&mut self, imports: &[Import], names: &mut NameStore, dir: Option<&Path>, file: Option<&Path>) -> Result<Vec<ModuleFunction>, Vec<Diagnostic>> {
+        let mut functions: Vec<ModuleFunction> = Vec::new();
+        let mut errors = Vec::new();
+        for import in imports {
+            let module_name = names.get(import.module).unwrap().clone();
+            let path = match self.find_module(&module_name, dir) {
+                Some(path) => path,
+                None => {
+                    let searched: Vec<String> = dir.into_iter().chain(self.search_paths.iter().map(PathBuf::as_path))
+                        .map(|d| if d.as_os_str().is_empty() { String::from(".") } else { d.display().to_string() })
+                        .collect();
+                    let note = if searched.is_empty() {
+                        String::from("there are no search paths, add one with -I")
+                    } else {
+                        format!("searched in {}", searched.join(", "))
+                    };
+                    errors.push(in_file(Diagnostic::error(format!("Module {} not found", module_name))
+                        .with_code(MODULE_NOT_FOUND)
+                        .with_primary(import.span, "")
+                        .with_note(note), file));
+                    continue;
+                }
+            };
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if !self.files.contains(&path) {
+                self.files.push(path.clone());
+            }
+
+            if let Some(position) = self.loading.iter().position(|p| *p == canonical) {
+                let cycle: Vec<String> = self.loading[position..].iter().chain(std::iter::once(&canonical)).map(|p| display_name(p)).collect();
+                errors.push(in_file(Diagnostic::error(format!("Importing {} creates a cycle", module_name))
+                    .with_code(IMPORT_CYCLE)
+                    .with_primary(import.span, "")
+                    .with_note(format!("the import cycle is {}", cycle.join(" -> "))), file));
+                continue;
+            }
+
+            if !self.modules.contains_key(&canonical) {
+                if let Err(e) = self.load(&path, &canonical) {
+                    errors.extend(e);
+                    continue;
+                }
+            }
+            let module = &self.modules[&canonical];
+
+            let selected: Vec<&ModuleFunction> = match &import.functions {
+                None => module.functions.iter().collect(),
+                Some(requested) => {
+                    let mut pending = Vec::new();
+                    for &f in requested {
+                        let function_name = names.get(f).unwrap();
+                        match module.names.get_by_interned(function_name).filter(|n| module.functions.iter().any(|mf| mf.name == *n)) {
+                            Some(name) => pending.push(name),
+                            None => errors.push(in_file(Diagnostic::error(format!("Module {} has no function {}", module_name, function_name))
+                                .with_code(UNKNOWN_IMPORT)
+                                .with_primary(import.span, ""), file))
+                        }
+                    }
+                    //The functions called by the imported ones are needed as well
+                    let mut selected: Vec<InternedName> = Vec::new();
+                    while let Some(name) = pending.pop() {
+                        if selected.contains(&name) {
+                            continue;
+                        }
+                        selected.push(name);
+                        let mut callees = Vec::new();
+                        if let Some(function) = module.functions.iter().find(|mf| mf.name == name) {
+                            collect_callees(std::slice::from_ref(&function.definition), &mut callees);
+                        }
+                        pending.extend(callees);
+                    }
+                    module.functions.iter().filter(|mf| selected.contains(&mf.name)).collect()
+                }
+            };
+
+            for function in selected {
+                let name = names.register(module.names.get(function.name).unwrap());
+                match functions.iter().find(|f| f.name == name) {
+                    Some(existing) if existing.origin == function.origin => (),
+                    Some(existing) => errors.push(in_file(Diagnostic::error(format!("Function {} is imported from both {} and {}", names.get(name).unwrap(), display_name(&existing.origin), display_name(&function.origin)))
+                        .with_code(IMPORT_CONFLICT)
+                        .with_primary(import.span, ""), file)),
+                    None => functions.push(ModuleFunction {
+                        name,
+                        definition: translate(&function.definition, &module.names, names),
+                        origin: function.origin.clone(),
+                    })
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(functions)
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn load(&mut self, path: &Path, canonical: &Path) -> Result<(), Vec<Diagnostic>> {
+        let source = fs::read_to_string(path).map_err(|e| vec![Diagnostic::error(format!("Cannot read module {}: {}", path.display(), e))])?;
+        let (mut names, module) = parser::parse_module(&source);
+        let module = module.map_err(|errors| errors.into_iter().map(|d| d.with_file(path)).collect::<Vec<Diagnostic>>())?;
+
+        self.loading.push(canonical.to_path_buf());
+        let functions = self.module_functions(module, &mut names, path.parent(), Some(path), canonical);
+        self.loading.pop();
+
+        self.modules.insert(canonical.to_path_buf(), LoadedModule { names, functions: functions? });
+        Ok(())
+    }
+
+    //Checks a module that is not imported, e.g. one that is open in an editor. Diagnostics without a file belong to the module.
+    pub fn check_module(&mut self, module: Module, names: &mut NameStore, file: Option<&Path>) -> Result<(), Vec<Diagnostic>> {
+        let canonical = file.and_then(|f| fs::canonicalize(f).ok()).unwrap_or_default();
+        self.loading.push(canonical.clone());
+        let functions = self.module_functions(module, names, file.and_then(Path::parent), None, &canonical);
+        self.loading.clear();
+        functions.map(|_| ())
+    }
+
+    //The imported and defined functions of a module. Modules are checked on their own, so errors are reported in the file they occur in.
+    fn module_functions(&mut self, module: Module, names: &mut NameStore, dir: Option<&Path>, file: Option<&Path>, origin: &Path) -> Result<Vec<ModuleFunction>, Vec<Diagnostic>> {
+        let mut functions = self.resolve_imports(&module.imports, names, dir, file)?;
+
+        let imported_count = functions.len();
+        let mut errors = Vec::new();
+        for definition in module.functions {
+            let (name, span) = match &definition {
+                Ast::Def { name, span, .. } => (*name, *span),
+                _ => unreachable!()
+            };
+            match functions.iter().position(|f| f.name == name) {
+                Some(i) if i < imported_count => errors.push(in_file(Diagnostic::error(format!("Function {} is already imported from {}", names.get(name).unwrap(), display_name(&functions[i].origin)))
+                    .with_code(IMPORT_CONFLICT)
+                    .with_primary(span, ""), file)),
+                //A later definition replaces an earlier one, like in programs
+                Some(i) => functions[i].definition = definition,
+                None => functions.push(ModuleFunction { name, definition, origin: origin.to_path_buf() })
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        //The prelude functions are not part of the module, programs importing it add them themselves
+        let mut body: Vec<Ast> = functions.iter().map(|f| f.definition.clone()).collect();
+        let prelude = self.add_prelude(&mut body, names);
+        let program = Program {
+            imports: Vec::new(),
+            body,
+            inputs: Vec::new(),
+            output: names.register("output"),
+            imported: functions[..imported_count].iter().map(|f| f.name).chain(prelude).collect(),
+        };
+        ir::convert_program_to_ir(&program, names, ir::DecrementPolicy::Wrap, OverflowPolicy::Wrap)
+            .map_err(|errors| errors.into_iter().map(|d| in_file(d, file)).collect::<Vec<Diagnostic>>())?;
+        Ok(functions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::resolver::Resolver;
+    use crate::parser::parse_program;
+    use crate::diagnostic::IMPORT_CYCLE;
+    use crate::{ir, interpreter};
+    use crate::codegen::OverflowPolicy;
+    use std::fs;
+
+    #[test]
+    fn test_resolve_imports() {
+        let dir = std::env::temp_dir().join(format!("minipython-resolver-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib/a.mpy"), "def add(a, b):\n    return a\ndef unused(a):\n    return a\n").unwrap();
+        fs::write(dir.join("lib/b.mpy"), "import a\ndef twice(a):\n    r=add(a, a)\n    return r\n").unwrap();
+        fs::write(dir.join("lib/c.mpy"), "import d\n").unwrap();
+        fs::write(dir.join("lib/d.mpy"), "from c import f\n").unwrap();
+        let search_paths = vec![dir.join("lib")];
+
+        //add is needed by twice and imported again directly, but only included once
+        let (mut names, program) = parse_program("from b import twice\nfrom a import add\ninput: x\noutput: y\ny=twice(x)\n");
+        let mut program = program.unwrap();
+        let mut resolver = Resolver::new(&search_paths);
+        resolver.resolve_program(&mut program, &mut names, None).unwrap();
+        let imported: Vec<&str> = program.imported.iter().map(|&f| names.get(f).unwrap().as_str()).collect();
+        assert_eq!(imported, vec!["add", "twice"]);
+        assert_eq!(resolver.module_files(), &[dir.join("lib/b.mpy"), dir.join("lib/a.mpy")]);
+        assert_eq!(program.body.len(), 3);
+        assert!(program.imports.is_empty());
+
+        let (mut names, program) = parse_program("import c\ninput: x\noutput: y\ny+=1\n");
+        let mut resolver = Resolver::new(&search_paths);
+        let errors = resolver.resolve_program(&mut program.unwrap(), &mut names, None).unwrap_err();
+        assert_eq!(resolver.module_files(), &[dir.join("lib/c.mpy"), dir.join("lib/d.mpy")]);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].code, Some(IMPORT_CYCLE));
+        assert_eq!(errors[0].file, Some(dir.join("lib/d.mpy")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prelude() {
+        //Only div and the functions it needs are added, the local add replaces the one of the prelude
+        let code = "input: a, b\noutput: q\ndef add(a, b):\n    return a\nq=div(a, b)\n";
+        let (mut names, program) = parse_program(code);
+        let mut program = program.unwrap();
+        Resolver::new(&[]).resolve_program(&mut program, &mut names, None).unwrap();
+        let imported: Vec<&str> = program.imported.iter().map(|&f| names.get(f).unwrap().as_str()).collect();
+        assert_eq!(imported, vec!["pred", "sub", "not", "and", "le", "div"]);
+        let ir = ir::convert_program_to_ir(&program, &names, ir::DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        assert_eq!(interpreter::run_program(&ir, &names, &[17, 5]).unwrap(), 3);
+        assert_eq!(interpreter::run_program(&ir, &names, &[17, 0]).unwrap(), 0);
+
+        let (mut names, program) = parse_program(code);
+        let mut program = program.unwrap();
+        Resolver::new(&[]).with_prelude(false).resolve_program(&mut program, &mut names, None).unwrap();
+        assert!(program.imported.is_empty());
+        assert!(ir::convert_program_to_ir(&program, &names, ir::DecrementPolicy::Wrap, OverflowPolicy::Wrap).is_err());
+    }
+}
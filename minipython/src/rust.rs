@@ -0,0 +1,205 @@
+use crate::ir::*;
+use crate::name::*;
+use crate::codegen::{CodegenOptions, IntegerWidth, OverflowPolicy};
+use std::error::Error;
+use std::io::Write;
+use crate::value::Value;
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+
+//The generated code is meant to be included into other crates, so it must not cause warnings there
+const RUST_ALLOWED_LINTS: &str = "#[allow(unused_mut, unused_variables, unused_assignments, unreachable_code, non_snake_case, clippy::all)]";
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self", "static",
+    "struct", "super", "trait", "true", "type", "unsafe", "use", "where", "while", "abstract", "become", "box", "do",
+    "final", "gen", "macro", "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+//Path keywords cannot be raw identifiers
+const RUST_NON_RAW_KEYWORDS: &[&str] = &["self", "Self", "crate", "super"];
+
+//The id suffix keeps keywords from being used as variable names, e.g. self_0
+fn to_value_name(v: Value, name_store: &NameStore) -> String {
+    format!("{}_{}", v.get_name(name_store).unwrap(), v.get_id())
+}
+
+//Values always have a suffix, but function names are used as they are.
+//Path keywords get underscores appended until the name is not used anywhere in the program.
+fn to_function_name(name: &str, name_store: &NameStore) -> String {
+    if RUST_NON_RAW_KEYWORDS.contains(&name) {
+        let mut renamed = format!("{}_", name);
+        while name_store.get_by_interned(&renamed).is_some() {
+            renamed.push('_');
+        }
+        renamed
+    } else if RUST_KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        String::from(name)
+    }
+}
+
+fn value_type(options: &CodegenOptions) -> &'static str {
+    match options.width {
+        IntegerWidth::W128 => "u128",
+        _ => "u64"
+    }
+}
+
+//With checked overflow, functions return None if an increment overflows
+fn return_type(options: &CodegenOptions) -> String {
+    match options.overflow {
+        OverflowPolicy::Trap => format!("Option<{}>", value_type(options)),
+        _ => String::from(value_type(options))
+    }
+}
+
+fn wrap_result(value: &str, options: &CodegenOptions) -> String {
+    match options.overflow {
+        OverflowPolicy::Trap => format!("Some({})", value),
+        _ => String::from(value)
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+fn compile_statement(statement: &IRStatement, name_store: &NameStore, options: &CodegenOptions, depth: usize, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let ind = indent(depth);
+    match statement {
+        ValueModify(val, change, _) => {
+            let val_name = to_value_name(*val, name_store);
+            let operation = match (*change >= 0, options.overflow, options.decrement) {
+                (true, OverflowPolicy::Wrap, _) => "wrapping_add",
+                (true, OverflowPolicy::Saturate, _) => "saturating_add",
+                (true, OverflowPolicy::Trap, _) => "checked_add",
+                (false, _, DecrementPolicy::Wrap) => "wrapping_sub",
+                (false, _, DecrementPolicy::Saturate) => "saturating_sub"
+            };
+            let checked = if operation == "checked_add" { "?" } else { "" };
+            writeln!(output, "{}{} = {}.{}({}){};", ind, val_name, val_name, operation, change.unsigned_abs(), checked)?;
+        },
+        Return(val, _) => {
+            writeln!(output, "{}return {};", ind, wrap_result(&to_value_name(*val, name_store), options))?;
+        },
+        Loop { condition_var, body, .. } => {
+            writeln!(output, "{}while {} != 0 {{", ind, to_value_name(*condition_var, name_store))?;
+
+            for st in body {
+                compile_statement(st, name_store, options, depth + 1, output)?;
+            }
+
+            writeln!(output, "{}}}", ind)?;
+        },
+        FunctionCall { func, args, target, .. } => {
+            let args_names = args.iter().map(|&v| to_value_name(v, name_store)).collect::<Vec<String>>().join(", ");
+            let checked = if options.overflow == OverflowPolicy::Trap { "?" } else { "" };
+            writeln!(output, "{}{} = {}({}){};", ind, to_value_name(*target, name_store), to_function_name(name_store.get(*func).unwrap(), name_store), args_names, checked)?;
+        }
+    }
+    Ok(())
+}
+
+fn compile_block(block: &IRBlock, name_store: &NameStore, options: &CodegenOptions, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    for &val in &block.values {
+        writeln!(output, "    let mut {}: {} = 0;", to_value_name(val, name_store), value_type(options))?;
+    }
+
+    for statement in &block.body {
+        compile_statement(statement, name_store, options, 1, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_signature(output: &mut dyn Write, name: &str, params: &[Value], name_store: &NameStore, options: &CodegenOptions) -> Result<(), Box<dyn Error>> {
+    let params = params.iter().map(|&v| format!("mut {}: {}", to_value_name(v, name_store), value_type(options))).collect::<Vec<String>>().join(", ");
+    writeln!(output, "{}", RUST_ALLOWED_LINTS)?;
+    writeln!(output, "pub fn {}({}) -> {} {{", name, params, return_type(options))?;
+    Ok(())
+}
+
+//Generates a module without I/O. The main block becomes a function main taking the inputs and returning the output.
+pub fn compile_to_rust(program: &IRProgram, name_store: &NameStore, options: &CodegenOptions, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if program.functions.iter().any(|(name, _)| name_store.get(*name).unwrap() == "main") {
+        return Err(Box::from("Function main conflicts with the main block in the generated Rust code"));
+    }
+
+    for (function_name, function) in &program.functions {
+        write_signature(output, &to_function_name(name_store.get(*function_name).unwrap(), name_store), &function.params, name_store, options)?;
+        compile_block(&function.body, name_store, options, output)?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+    }
+
+    write_signature(output, "main", &program.inputs, name_store, options)?;
+    writeln!(output, "    let mut {}: {} = 0;", to_value_name(program.output, name_store), value_type(options))?;
+    compile_block(&program.main, name_store, options, output)?;
+    writeln!(output, "    {}", wrap_result(&to_value_name(program.output, name_store), options))?;
+    writeln!(output, "}}")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::CodegenOptions;
+    use crate::compiler::{compile_to_string, CheckOptions, Target};
+    use std::process::Command;
+    use std::fs;
+
+    #[test]
+    fn test_rust_keywords() {
+        let code =
+            "input: self
+output: crate
+def Self(super):
+    super+=1
+    return super
+#enddef
+def fn(self):
+    self=Self(self)
+    return self
+#enddef
+crate=fn(self)
+";
+        let (module, _) = compile_to_string(code, Target::Rust, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        assert!(module.contains("pub fn Self_(mut super_"));
+        assert!(module.contains("pub fn r#fn(mut self_"));
+        compile_module("keywords", &module);
+    }
+
+    #[test]
+    fn test_rust_renamed_keyword_collision() {
+        let code =
+            "input: x
+output: y
+def self(a):
+    a+=1
+    return a
+#enddef
+def self_(a):
+    a=self(a)
+    return a
+#enddef
+y=self_(x)
+";
+        let (module, _) = compile_to_string(code, Target::Rust, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        assert!(module.contains("pub fn self__(mut a_"));
+        assert!(module.contains("pub fn self_(mut a_"));
+        compile_module("collision", &module);
+    }
+
+    //Compiles the generated module as a library with rustc
+    fn compile_module(name: &str, module: &str) {
+        let dir = std::env::temp_dir().join(format!("minipython-rust-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("generated.rs"), module).unwrap();
+        let compiled = Command::new("rustc").arg("--crate-type").arg("lib").arg("--edition").arg("2018")
+            .arg("--out-dir").arg(&dir).arg(dir.join("generated.rs")).output().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(compiled.status.success(), "rustc failed: {}", String::from_utf8_lossy(&compiled.stderr));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::bytecode::{BytecodeProgram, BytecodeFunction, Instruction};
+
+const MAX_CALL_DEPTH: usize = 1_000_000;
+
+struct Frame<'a> {
+    function: &'a BytecodeFunction,
+    locals: Vec<u64>,
+    pc: usize,
+    //Height of the operand stack when the function was entered
+    stack_base: usize,
+}
+
+impl<'a> Frame<'a> {
+    fn new(function: &'a BytecodeFunction, args: Vec<u64>, stack_base: usize) -> Self {
+        let mut locals = args;
+        locals.resize(function.local_count as usize, 0);
+        Frame {
+            function,
+            locals,
+            pc: 0,
+            stack_base,
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<u64>) -> Result<u64, String> {
+    stack.pop().ok_or_else(|| String::from("Operand stack underflow"))
+}
+
+pub fn execute(program: &BytecodeProgram, inputs: &[u64]) -> Result<u64, String> {
+    if program.input_names.len() != inputs.len() {
+        return Err(format!("Program requires {} inputs, but got {}", program.input_names.len(), inputs.len()));
+    }
+
+    let mut stack: Vec<u64> = Vec::new();
+    let mut frames = vec![Frame::new(&program.main, inputs.to_vec(), 0)];
+
+    loop {
+        let frame = frames.last_mut().unwrap();
+        let instr = match frame.function.code.get(frame.pc) {
+            Some(instr) => *instr,
+            None => return Err(format!("Function {} did not return a value", frame.function.name))
+        };
+        frame.pc += 1;
+
+        match instr {
+            Instruction::Load(slot) => stack.push(frame.locals[slot as usize]),
+            Instruction::Store(slot) => frame.locals[slot as usize] = pop(&mut stack)?,
+            Instruction::Const(c) => stack.push(c as u64),
+            Instruction::Add => {
+                let b = pop(&mut stack)?;
+                let a = pop(&mut stack)?;
+                stack.push(a.wrapping_add(b));
+            },
+            Instruction::Jump(target) => frame.pc = target as usize,
+            Instruction::JumpIfZero(target) => {
+                if pop(&mut stack)? == 0 {
+                    frame.pc = target as usize;
+                }
+            },
+            Instruction::Call(func) => {
+                let function = &program.functions[func as usize];
+                let param_count = function.param_count as usize;
+                if stack.len() < frame.stack_base + param_count {
+                    return Err(String::from("Operand stack underflow"));
+                }
+                if frames.len() >= MAX_CALL_DEPTH {
+                    return Err(String::from("Call stack overflow"));
+                }
+                let args = stack.split_off(stack.len() - param_count);
+                let stack_base = stack.len();
+                frames.push(Frame::new(function, args, stack_base));
+            },
+            Instruction::Return => {
+                let result = pop(&mut stack)?;
+                let frame = frames.pop().unwrap();
+                stack.truncate(frame.stack_base);
+                if frames.is_empty() {
+                    return Ok(result);
+                }
+                stack.push(result);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bytecode::{generate_bytecode, BytecodeProgram};
+    use crate::vm::execute;
+    use crate::ir::{convert_program_to_ir, DecrementPolicy};
+    use crate::codegen::OverflowPolicy;
+    use crate::parser::parse_program;
+
+    fn compile(code: &str) -> BytecodeProgram {
+        let (store, ast_res) = parse_program(code);
+        let ir = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        generate_bytecode(&ir, &store)
+    }
+
+    const ARITHMETIC: &str =
+        "input: x1, x2
+output: a
+
+def add(x, y):
+    while x!=0:
+        n+=1
+        x-=1
+    while y!=0:
+        n+=1
+        y-=1
+    return n
+
+def mul(a, b):
+    while b!=0:
+        n=add(n, a)
+        b-=1
+    return n
+
+def exp(a, b):
+    n+=1
+    while b!=0:
+        n=mul(n, a)
+        b-=1
+    return n
+
+a=exp(x1, x2)";
+
+    #[test]
+    fn test_execute() {
+        let program = compile(ARITHMETIC);
+        assert_eq!(execute(&program, &[2, 10]), Ok(1024));
+        assert_eq!(execute(&program, &[3, 4]), Ok(81));
+        assert!(execute(&program, &[3]).is_err());
+    }
+
+    #[test]
+    fn test_return_in_loop() {
+        let code =
+            "input: x
+output: y
+def first(a):
+    while a!=0:
+        return a
+    return a
+y=first(x)";
+        let program = compile(code);
+        assert_eq!(execute(&program, &[5]), Ok(5));
+        assert_eq!(execute(&program, &[0]), Ok(0));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let program = compile(ARITHMETIC);
+        let bytes = program.to_bytes();
+        let read = BytecodeProgram::from_bytes(&bytes);
+        assert_eq!(read, Ok(program));
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let mut bytes = compile(ARITHMETIC).to_bytes();
+        assert!(BytecodeProgram::from_bytes(b"ELF").is_err());
+        assert!(BytecodeProgram::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        //Version number
+        bytes[4] = 99;
+        assert!(BytecodeProgram::from_bytes(&bytes).is_err());
+    }
+}
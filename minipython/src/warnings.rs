@@ -0,0 +1,460 @@
+use crate::ir::*;
+use crate::name::*;
+use crate::value::Value;
+use crate::lexer::Span;
+use crate::diagnostic::{Diagnostic, Severity, UNUSED_VARIABLE, UNINITIALIZED_READ, DEAD_STORE, UNUSED_FUNCTION, UNREACHABLE_CODE, DEAD_LOOP, RECURSION};
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+use crate::callgraph::CallGraph;
+use std::collections::{HashMap, BTreeSet};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct CheckOptions {
+    pub warnings_as_errors: bool,
+    //Recursion is allowed, but usually unintended as there are no conditionals to end it
+    pub deny_recursion: bool,
+}
+
+impl CheckOptions {
+    //Applies the options to the warnings of a program, failing if any of them is turned into an error
+    pub fn apply(&self, mut warnings: Vec<Diagnostic>) -> Result<Vec<Diagnostic>, Vec<Diagnostic>> {
+        for warning in &mut warnings {
+            if self.warnings_as_errors || (self.deny_recursion && warning.code == Some(RECURSION)) {
+                warning.severity = Severity::Error;
+            }
+        }
+        if warnings.iter().any(Diagnostic::is_error) {
+            Err(warnings)
+        } else {
+            Ok(warnings)
+        }
+    }
+}
+
+//Where a value is first written and first read. Increments count as writes only,
+//as a value that is only incremented does not influence the result.
+#[derive(Default)]
+struct Usage {
+    write: Option<Span>,
+    read: Option<Span>,
+}
+
+fn collect_usage(statements: &[IRStatement], usage: &mut HashMap<Value, Usage>) {
+    let read = |usage: &mut HashMap<Value, Usage>, v: Value, span: Span| {
+        usage.entry(v).or_default().read.get_or_insert(span);
+    };
+    for statement in statements {
+        match statement {
+            ValueModify(val, _, span) => {
+                usage.entry(*val).or_default().write.get_or_insert(*span);
+            },
+            Return(val, span) => read(usage, *val, *span),
+            FunctionCall { args, target, span, .. } => {
+                for &arg in args {
+                    read(usage, arg, *span);
+                }
+                usage.entry(*target).or_default().write.get_or_insert(*span);
+            },
+            Loop { condition_var, body, span } => {
+                read(usage, *condition_var, *span);
+                collect_usage(body, usage);
+            }
+        }
+    }
+}
+
+fn reads(statements: &[IRStatement], v: Value) -> bool {
+    statements.iter().any(|statement| match statement {
+        ValueModify(..) => false,
+        Return(val, _) => *val == v,
+        FunctionCall { args, .. } => args.contains(&v),
+        Loop { condition_var, body, .. } => *condition_var == v || reads(body, v)
+    })
+}
+
+//Looks for the statement overwriting an assigned value before it is read.
+//Writes inside loops are ignored, as the loop might not run.
+fn overwritten_by(statements: &[IRStatement], v: Value) -> Option<Span> {
+    for statement in statements {
+        match statement {
+            ValueModify(..) => (),
+            Return(..) => return None,
+            FunctionCall { args, target, span, .. } => {
+                if args.contains(&v) {
+                    return None;
+                }
+                if *target == v {
+                    return Some(*span);
+                }
+            },
+            Loop { condition_var, body, .. } => {
+                if *condition_var == v || reads(body, v) {
+                    return None;
+                }
+            }
+        }
+    }
+    None
+}
+
+fn check_dead_stores(statements: &[IRStatement], name_store: &NameStore, warnings: &mut Vec<Diagnostic>) {
+    for (i, statement) in statements.iter().enumerate() {
+        match statement {
+            FunctionCall { target, span, .. } => {
+                if let Some(overwrite) = overwritten_by(&statements[i + 1..], *target) {
+                    let name = target.get_name(name_store).unwrap();
+                    warnings.push(Diagnostic::warning(format!("Value assigned to {} is never read", name))
+                        .with_code(DEAD_STORE)
+                        .with_primary(*span, "")
+                        .with_secondary(overwrite, format!("{} is overwritten here", name)));
+                }
+            },
+            Loop { body, .. } => check_dead_stores(body, name_store, warnings),
+            _ => ()
+        }
+    }
+}
+
+//Inputs are the parameters of a function or the inputs of the program, they count as written.
+//Outputs are the output of the program, they count as read.
+fn check_block(block: &IRBlock, inputs: &[Value], outputs: &[Value], definition: Option<Span>, name_store: &NameStore, warnings: &mut Vec<Diagnostic>) {
+    let mut usage = HashMap::new();
+    collect_usage(&block.body, &mut usage);
+
+    for &input in inputs {
+        let name = input.get_name(name_store).unwrap();
+        let read = usage.get(&input).and_then(|u| u.read).is_some() || outputs.contains(&input);
+        if !read && !name.starts_with('_') {
+            let warning = Diagnostic::warning(format!("Input {} is never read", name)).with_code(UNUSED_VARIABLE);
+            warnings.push(match definition {
+                Some(span) => warning.with_primary(span, ""),
+                None => warning
+            });
+        }
+    }
+
+    for &output in outputs {
+        let written = inputs.contains(&output) || usage.get(&output).and_then(|u| u.write).is_some();
+        if !written {
+            warnings.push(Diagnostic::warning(format!("Output {} is never assigned and always zero", output.get_name(name_store).unwrap()))
+                .with_code(UNINITIALIZED_READ));
+        }
+    }
+
+    for &val in &block.values {
+        let name = val.get_name(name_store).unwrap();
+        let (write, read) = match usage.get(&val) {
+            Some(u) => (u.write, u.read),
+            None => continue
+        };
+        match (write, read) {
+            (Some(span), None) if !name.starts_with('_') => warnings.push(Diagnostic::warning(format!("Variable {} is assigned but never read", name))
+                .with_code(UNUSED_VARIABLE)
+                .with_primary(span, "")),
+            (None, Some(span)) => warnings.push(Diagnostic::warning(format!("Variable {} is never assigned and always zero", name))
+                .with_code(UNINITIALIZED_READ)
+                .with_primary(span, "")
+                .with_note("variables start at zero, check the name for typos")),
+            _ => ()
+        }
+    }
+
+    check_dead_stores(&block.body, name_store, warnings);
+}
+
+//Code that never runs, sorted by position
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
+pub struct DeadCode {
+    //Functions that are not called from the main program, directly or through other functions
+    pub unused_functions: Vec<(String, Span)>,
+    //Statements following a return, one span per block
+    pub unreachable: Vec<Span>,
+    //Loops whose condition variable is always zero when they are reached, with the name of the variable
+    pub dead_loops: Vec<(String, Span)>,
+}
+
+fn find_unreachable(statements: &[IRStatement], unreachable: &mut Vec<Span>) {
+    if let Some(i) = statements.iter().position(|st| matches!(st, Return(..))) {
+        if let Some(first) = statements.get(i + 1) {
+            unreachable.push(statements[i + 1..].iter().fold(first.span(), |span, st| span.merge(st.span())));
+        }
+    }
+    for statement in statements {
+        if let Loop { body, .. } = statement {
+            find_unreachable(body, unreachable);
+        }
+    }
+}
+
+fn collect_writes(statements: &[IRStatement], writes: &mut BTreeSet<Value>) {
+    for statement in statements {
+        match statement {
+            ValueModify(val, _, _) => { writes.insert(*val); },
+            FunctionCall { target, .. } => { writes.insert(*target); },
+            Loop { body, .. } => collect_writes(body, writes),
+            Return(..) => ()
+        }
+    }
+}
+
+//Tracks which values are known to be zero. Any write makes a value unknown, even decrements that
+//saturate at zero or increments that overflow to zero, so a loop is only reported if it is certainly dead.
+fn find_dead_loops(statements: &[IRStatement], zero: &mut BTreeSet<Value>, name_store: &NameStore, dead_loops: &mut Vec<(String, Span)>) {
+    for statement in statements {
+        match statement {
+            ValueModify(val, _, _) => { zero.remove(val); },
+            FunctionCall { target, .. } => { zero.remove(target); },
+            Return(..) => return,
+            Loop { condition_var, body, span } => {
+                if zero.contains(condition_var) {
+                    dead_loops.push((condition_var.get_name(name_store).unwrap().clone(), *span));
+                    continue;
+                }
+                //Values written anywhere in the body might be nonzero at the start of later iterations
+                let mut writes = BTreeSet::new();
+                collect_writes(body, &mut writes);
+                zero.retain(|v| !writes.contains(v));
+                find_dead_loops(body, &mut zero.clone(), name_store, dead_loops);
+                zero.insert(*condition_var);
+            }
+        }
+    }
+}
+
+//Variables start at zero, except for inputs and parameters
+fn initially_zero(block: &IRBlock, extra: &[Value]) -> BTreeSet<Value> {
+    block.values.iter().chain(extra).copied().collect()
+}
+
+pub fn find_dead_code(program: &IRProgram, name_store: &NameStore) -> DeadCode {
+    let mut dead = DeadCode::default();
+    let used = CallGraph::new(program).reachable_from_main();
+    //Imported functions belong to other files, and a module is usually not used completely
+    for (name, function) in program.functions.iter().filter(|(_, f)| !f.imported) {
+        let text = name_store.get(*name).unwrap();
+        if !used.contains(name) && !text.starts_with('_') {
+            dead.unused_functions.push((text.clone(), function.span));
+        }
+        find_unreachable(&function.body.body, &mut dead.unreachable);
+        find_dead_loops(&function.body.body, &mut initially_zero(&function.body, &[]), name_store, &mut dead.dead_loops);
+    }
+    find_dead_loops(&program.main.body, &mut initially_zero(&program.main, &[program.output]), name_store, &mut dead.dead_loops);
+
+    dead.unused_functions.sort_by_key(|(_, span)| span.start.pos);
+    dead.unreachable.sort_by_key(|span| span.start.pos);
+    dead.dead_loops.sort_by_key(|(_, span)| span.start.pos);
+    dead
+}
+
+fn dead_code_warnings(dead: &DeadCode, warnings: &mut Vec<Diagnostic>) {
+    for (name, span) in &dead.unused_functions {
+        warnings.push(Diagnostic::warning(format!("Function {} is never called", name))
+            .with_code(UNUSED_FUNCTION)
+            .with_primary(*span, ""));
+    }
+    for span in &dead.unreachable {
+        warnings.push(Diagnostic::warning("Unreachable statement")
+            .with_code(UNREACHABLE_CODE)
+            .with_primary(*span, "")
+            .with_note("statements after a `return` are never executed"));
+    }
+    for (name, span) in &dead.dead_loops {
+        warnings.push(Diagnostic::warning(format!("Loop never runs, as {} is always zero here", name))
+            .with_code(DEAD_LOOP)
+            .with_primary(*span, ""));
+    }
+}
+
+fn check_recursion(program: &IRProgram, name_store: &NameStore, warnings: &mut Vec<Diagnostic>) {
+    let graph = CallGraph::new(program);
+    for group in graph.recursive_groups() {
+        //Recursion within a module is reported where it is defined, only new cycles are reported here
+        if group.iter().all(|&f| program.function(f).unwrap().imported) {
+            continue;
+        }
+        let name = |f: InternedName| name_store.get(f).unwrap();
+        let message = match group.as_slice() {
+            [function] => format!("Function {} calls itself", name(*function)),
+            _ => format!("Functions {} call each other", group.iter().map(|&f| name(f).as_str()).collect::<Vec<&str>>().join(", "))
+        };
+        //Spans of imported functions are in another file, so the labels only point into the program
+        let imported = |f: InternedName| program.function(f).unwrap().imported;
+        let first_local = *group.iter().find(|&&f| !imported(f)).unwrap();
+        let mut warning = Diagnostic::warning(message)
+            .with_code(RECURSION)
+            .with_primary(program.function(first_local).unwrap().span, "");
+        for &caller in &group {
+            if imported(caller) {
+                warning = warning.with_note(format!("{} is imported", name(caller)));
+                continue;
+            }
+            for call in graph.calls(caller).iter().filter(|call| group.contains(&call.callee)) {
+                warning = warning.with_secondary(call.span, format!("{} calls {} here", name(caller), name(call.callee)));
+            }
+        }
+        warnings.push(warning);
+    }
+}
+
+pub fn check_program(program: &IRProgram, name_store: &NameStore) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+    for (_, function) in program.functions.iter().filter(|(_, f)| !f.imported) {
+        check_block(&function.body, &function.params, &[], Some(function.span), name_store, &mut warnings);
+    }
+    check_block(&program.main, &program.inputs, &[program.output], None, name_store, &mut warnings);
+    dead_code_warnings(&find_dead_code(program, name_store), &mut warnings);
+    check_recursion(program, name_store, &mut warnings);
+    warnings.sort_by_key(|d| d.primary.as_ref().map(|l| (l.span.start.pos, l.span.end.pos)));
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::warnings::{check_program, CheckOptions};
+    use crate::ir::{convert_program_to_ir, DecrementPolicy};
+    use crate::codegen::OverflowPolicy;
+    use crate::parser::parse_program;
+    use crate::resolver::Resolver;
+
+    fn warnings(code: &str) -> Vec<String> {
+        let (store, ast_res) = parse_program(code);
+        let ir = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        check_program(&ir, &store).iter().map(|d| format!("{}", d)).collect()
+    }
+
+    #[test]
+    fn test_no_warnings() {
+        let code =
+            "input: x1, x2
+output: a
+def add(x, y):
+    while x!=0:
+        n+=1
+        x-=1
+    while y!=0:
+        n+=1
+        y-=1
+    return n
+a=add(x1, x2)";
+        assert!(warnings(code).is_empty());
+    }
+
+    #[test]
+    fn test_variable_warnings() {
+        let code =
+            "input: x
+output: y
+def f(a, _b):
+    c+=1
+    return a
+y=f(x, resutl)
+_unused+=1";
+        assert_eq!(warnings(code), vec![
+            "4:5: warning[W0001]: Variable c is assigned but never read",
+            "6:1: warning[W0002]: Variable resutl is never assigned and always zero"
+        ]);
+    }
+
+    #[test]
+    fn test_unused_input_and_output() {
+        let code =
+            "input: x
+output: y
+";
+        assert_eq!(warnings(code), vec![
+            "warning[W0001]: Input x is never read",
+            "warning[W0002]: Output y is never assigned and always zero"
+        ]);
+    }
+
+    #[test]
+    fn test_dead_stores() {
+        let code =
+            "input: x
+output: y
+def id(a):
+    return a
+y=id(x)
+y=id(x)
+z=id(x)
+while z!=0:
+    z=id(y)
+    z=id(x)
+y=id(z)";
+        assert_eq!(warnings(code), vec![
+            "5:1: warning[W0003]: Value assigned to y is never read",
+            "9:5: warning[W0003]: Value assigned to z is never read"
+        ]);
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let code =
+            "input: x
+output: y
+def id(a):
+    return a
+def g(a):
+    return a
+def h(a):
+    b=g(a)
+    return b
+    b+=1
+def _helper(a):
+    return a
+while x!=0:
+    x-=1
+    y+=1
+while x!=0:
+    y+=1
+while t!=0:
+    t-=1
+y=id(y)";
+        assert_eq!(warnings(code), vec![
+            "5:1: warning[W0004]: Function g is never called",
+            "7:1: warning[W0004]: Function h is never called",
+            "10:5: warning[W0005]: Unreachable statement",
+            "16:1: warning[W0006]: Loop never runs, as x is always zero here",
+            "18:1: warning[W0006]: Loop never runs, as t is always zero here"
+        ]);
+    }
+
+    #[test]
+    fn test_recursion() {
+        let code =
+            "input: x
+output: y
+def f(n):
+    while n!=0:
+        n-=1
+        r=g(n)
+    return r
+def g(n):
+    r=f(n)
+    return r
+y=f(x)";
+        assert_eq!(warnings(code), vec!["3:1: warning[W0007]: Functions f, g call each other"]);
+
+        let (store, ast_res) = parse_program(code);
+        let ir = convert_program_to_ir(&ast_res.unwrap(), &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        let options = CheckOptions { deny_recursion: true, ..CheckOptions::default() };
+        let errors = options.apply(check_program(&ir, &store)).unwrap_err();
+        assert!(errors[0].is_error());
+        assert_eq!(errors[0].secondary.len(), 2);
+    }
+
+    #[test]
+    fn test_recursion_through_prelude() {
+        //mul of the prelude calls the local add, which replaces the one of the prelude
+        let code = "input: x\noutput: y\ndef add(a, b):\n    r=mul(a, b)\n    return r\ny=add(x, x)\n";
+        let (mut store, program) = parse_program(code);
+        let mut program = program.unwrap();
+        Resolver::new(&[]).resolve_program(&mut program, &mut store, None).unwrap();
+        let ir = convert_program_to_ir(&program, &store, DecrementPolicy::Wrap, OverflowPolicy::Wrap).unwrap();
+        let warnings = check_program(&ir, &store);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].primary.as_ref().unwrap().span.start.line, 3);
+        assert_eq!(warnings[0].secondary.len(), 1);
+        assert_eq!(warnings[0].secondary[0].span.start.line, 4);
+        assert_eq!(warnings[0].notes, vec!["mul is imported"]);
+    }
+}
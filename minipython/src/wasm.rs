@@ -0,0 +1,188 @@
+use crate::ir::*;
+use crate::name::*;
+use std::error::Error;
+use std::io::Write;
+use crate::value::Value;
+use crate::ir::IRStatement::{ValueModify, Return, Loop, FunctionCall};
+
+const WASM_VALUE_TYPE: &str = "i64";
+
+fn to_value_name(v: Value, name_store: &NameStore) -> String {
+    format!("${}_{}", v.get_name(name_store).unwrap(), v.get_id())
+}
+
+//Functions are exported under their own name, the prefix keeps their identifiers apart from $main
+fn to_function_name(name: InternedName, name_store: &NameStore) -> String {
+    format!("$mp_{}", name_store.get(name).unwrap())
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+fn write_locals(output: &mut dyn Write, values: &[Value], name_store: &NameStore) -> Result<(), Box<dyn Error>> {
+    //Locals are always initialized to 0 in WebAssembly
+    for &val in values {
+        writeln!(output, "    (local {} {})", to_value_name(val, name_store), WASM_VALUE_TYPE)?;
+    }
+    Ok(())
+}
+
+fn compile_statement(statement: &IRStatement, name_store: &NameStore, next_label: &mut u64, depth: usize, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let ind = indent(depth);
+    match statement {
+        ValueModify(val, change, _) => {
+            let val_name = to_value_name(*val, name_store);
+            writeln!(output, "{}local.get {}", ind, val_name)?;
+            writeln!(output, "{}{}.const {}", ind, WASM_VALUE_TYPE, change)?;
+            writeln!(output, "{}{}.add", ind, WASM_VALUE_TYPE)?;
+            writeln!(output, "{}local.set {}", ind, val_name)?;
+        },
+        Return(val, _) => {
+            writeln!(output, "{}local.get {}", ind, to_value_name(*val, name_store))?;
+            writeln!(output, "{}return", ind)?;
+        },
+        Loop { condition_var, body, .. } => {
+            let id = *next_label;
+            *next_label += 1;
+            writeln!(output, "{}block $loop_end_{}", ind, id)?;
+            writeln!(output, "{}  loop $loop_{}", ind, id)?;
+            writeln!(output, "{}    local.get {}", ind, to_value_name(*condition_var, name_store))?;
+            writeln!(output, "{}    {}.eqz", ind, WASM_VALUE_TYPE)?;
+            writeln!(output, "{}    br_if $loop_end_{}", ind, id)?;
+
+            for st in body {
+                compile_statement(st, name_store, next_label, depth + 2, output)?;
+            }
+
+            writeln!(output, "{}    br $loop_{}", ind, id)?;
+            writeln!(output, "{}  end", ind)?;
+            writeln!(output, "{}end", ind)?;
+        },
+        FunctionCall { func, args, target, .. } => {
+            for &arg in args {
+                writeln!(output, "{}local.get {}", ind, to_value_name(arg, name_store))?;
+            }
+            writeln!(output, "{}call {}", ind, to_function_name(*func, name_store))?;
+            writeln!(output, "{}local.set {}", ind, to_value_name(*target, name_store))?;
+        }
+    }
+    Ok(())
+}
+
+fn compile_block(block: &IRBlock, name_store: &NameStore, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    let mut next_label = 0;
+
+    for statement in &block.body {
+        compile_statement(statement, name_store, &mut next_label, 2, output)?;
+    }
+
+    Ok(())
+}
+
+fn write_params(output: &mut dyn Write, params: &[Value], name_store: &NameStore) -> Result<(), Box<dyn Error>> {
+    for &param in params {
+        write!(output, " (param {} {})", to_value_name(param, name_store), WASM_VALUE_TYPE)?;
+    }
+    writeln!(output, " (result {})", WASM_VALUE_TYPE)?;
+    Ok(())
+}
+
+pub fn compile_to_wat(program: &IRProgram, name_store: &NameStore, output: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+    if program.functions.iter().any(|(name, _)| name_store.get(*name).unwrap() == "main") {
+        return Err(Box::from("Function main conflicts with the export of the main block in the generated WebAssembly module"));
+    }
+
+    writeln!(output, "(module")?;
+
+    for (function_name, function) in &program.functions {
+        write!(output, "  (func {} (export \"{}\")", to_function_name(*function_name, name_store), name_store.get(*function_name).unwrap())?;
+        write_params(output, &function.params, name_store)?;
+        write_locals(output, &function.body.values, name_store)?;
+
+        compile_block(&function.body, name_store, output)?;
+
+        //Every function returns explicitly, so the end of the body is never reached
+        writeln!(output, "    unreachable")?;
+        writeln!(output, "  )")?;
+    }
+
+    write!(output, "  (func $main (export \"main\")")?;
+    write_params(output, &program.inputs, name_store)?;
+    write_locals(output, &[program.output], name_store)?;
+    write_locals(output, &program.main.values, name_store)?;
+
+    compile_block(&program.main, name_store, output)?;
+
+    writeln!(output, "    local.get {}", to_value_name(program.output, name_store))?;
+    writeln!(output, "  )")?;
+    writeln!(output, ")")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codegen::CodegenOptions;
+    use crate::compiler::{compile_to_string, CheckOptions, Target};
+
+    #[test]
+    fn test_wat_golden() {
+        let code =
+            "input: x
+output: y
+def f(a):
+    while a!=0:
+        a-=1
+        return a
+    #endwhile
+    return a
+#enddef
+y=f(x)
+y+=1
+";
+        let expected =
+            "(module
+  (func $mp_f (export \"f\") (param $a_2 i64) (result i64)
+    block $loop_end_0
+      loop $loop_0
+        local.get $a_2
+        i64.eqz
+        br_if $loop_end_0
+        local.get $a_2
+        i64.const -1
+        i64.add
+        local.set $a_2
+        local.get $a_2
+        return
+        br $loop_0
+      end
+    end
+    local.get $a_2
+    return
+    unreachable
+  )
+  (func $main (export \"main\") (param $x_0 i64) (result i64)
+    (local $y_1 i64)
+    local.get $x_0
+    call $mp_f
+    local.set $y_1
+    local.get $y_1
+    i64.const 1
+    i64.add
+    local.set $y_1
+    local.get $y_1
+  )
+)
+";
+        let (wat, _) = compile_to_string(code, Target::Wat, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap();
+        assert_eq!(wat, expected);
+    }
+
+    #[test]
+    fn test_wat_main_function() {
+        let code = "input: x\noutput: y\ndef main(a):\n    return a\n#enddef\ny=main(x)\n";
+        let errors = compile_to_string(code, Target::Wat, &CodegenOptions::default(), &CheckOptions::default(), &[]).unwrap_err();
+        assert_eq!(errors[0].message, "Function main conflicts with the export of the main block in the generated WebAssembly module");
+    }
+}